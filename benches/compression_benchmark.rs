@@ -0,0 +1,139 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{distributions::Alphanumeric, Rng};
+use rkv::store::lsm_store::KVStore;
+use rkv::store::options::{Compression, Options, TableOptions};
+use std::env;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn rand_string(l: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(l)
+        .map(char::from)
+        .collect()
+}
+
+/// Values that compress about as well as text: a small alphabet with repeats.
+fn rand_value(l: usize) -> String {
+    let words = ["alpha", "beta", "gamma", "delta", "epsilon"];
+    let mut rng = rand::thread_rng();
+    let mut value = String::with_capacity(l);
+    while value.len() < l {
+        value.push_str(words[rng.gen_range(0..words.len())]);
+        value.push(' ');
+    }
+    value.truncate(l);
+    value
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let mut size = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let metadata = entry.metadata().unwrap();
+        if metadata.is_dir() {
+            size += dir_size(&entry.path());
+        } else {
+            size += metadata.len();
+        }
+    }
+    size
+}
+
+/// Write the same data with every codec, report the bytes on disk and how
+/// long writes and reads take.
+pub fn compression_benchmarks(c: &mut Criterion) {
+    println!("Benchmark compression ...");
+    let default_n_keys = 20_000;
+    let n_keys = match env::var("N_KEYS") {
+        Ok(env_n_keys) => env_n_keys.parse().unwrap_or(default_n_keys),
+        Err(_) => default_n_keys,
+    };
+
+    let value_length: usize = match env::var("VALUE_LENGTH") {
+        Ok(value_length) => value_length.parse().unwrap_or(500),
+        Err(_) => 500,
+    };
+
+    let pairs: Vec<(String, String)> = (0..n_keys)
+        .map(|_| (rand_string(16), rand_value(value_length)))
+        .collect();
+    let bytes_per_table = 4 * 1024 * 1024;
+
+    let mut group = c.benchmark_group(format!(
+        "compression/{}-values-ofsize-{}-each",
+        n_keys, value_length
+    ));
+    group.significance_level(0.1).sample_size(20);
+
+    for compression in [
+        Compression::None,
+        Compression::Lz4,
+        Compression::Zstd,
+        Compression::Snappy,
+    ] {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let options = Options {
+            table: TableOptions {
+                compression,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            format!("{:?}", compression),
+            bytes_per_table,
+            path.clone(),
+            options,
+        );
+
+        group.throughput(Throughput::Bytes((16 + value_length) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("set", format!("{:?}", compression)),
+            &pairs,
+            |b, pairs| {
+                let mut i = 0;
+                b.iter(|| {
+                    let (k, v) = &pairs[i % pairs.len()];
+                    store.set(k.as_bytes(), v.as_bytes()).unwrap();
+                    i += 1;
+                })
+            },
+        );
+
+        for (k, v) in pairs.iter() {
+            store.set(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+        store.flush_memtable().unwrap();
+        println!(
+            "{:?}: {} bytes on disk for {} pairs",
+            compression,
+            dir_size(&path),
+            n_keys
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("get", format!("{:?}", compression)),
+            &pairs,
+            |b, pairs| {
+                let mut i = 0;
+                b.iter(|| {
+                    let (k, _) = &pairs[(i * 7919) % pairs.len()];
+                    i += 1;
+                    store.get(k.as_bytes())
+                })
+            },
+        );
+
+        drop(store);
+        temp_dir.close().unwrap();
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, compression_benchmarks);
+criterion_main!(benches);
@@ -81,7 +81,7 @@ pub fn get_benchmarks(c: &mut Criterion) {
                         Err(e) => panic!("Poisoned lock: {:?}", e),
                     }
                 }
-                store.set(k.as_bytes(), k.as_bytes());
+                store.set(k.as_bytes(), k.as_bytes()).unwrap();
                 match ctr.lock() {
                     Ok(mut ctr) => {
                         *ctr += 1;
@@ -124,6 +124,13 @@ pub fn get_benchmarks(c: &mut Criterion) {
         Err(e) => panic!("Poisoned lock: {:?}", e),
     }
 
+    // Keys the store never saw, answered by the bloom filters.
+    let missing_key = rand_string(key_length);
+    group.throughput(Throughput::Bytes(missing_key.len() as u64));
+    group.bench_with_input(BenchmarkId::new("missing", 1), &missing_key, |b, k| {
+        b.iter(|| store.get(k.as_bytes()))
+    });
+
     group.finish();
     drop(path);
     temp_dir.close().unwrap();
@@ -194,7 +201,7 @@ pub fn set_benchmark(c: &mut Criterion) {
 
             for _ in start..end {
                 let k = rand_string(key_length);
-                store.set(k.as_bytes(), k.as_bytes());
+                store.set(k.as_bytes(), k.as_bytes()).unwrap();
                 match ctr.lock() {
                     Ok(mut ctr) => {
                         *ctr += 1;
@@ -213,7 +220,7 @@ pub fn set_benchmark(c: &mut Criterion) {
         group.throughput(Throughput::Bytes(k.len() as u64));
         group.bench_with_input(BenchmarkId::from_parameter(n_keys - i), &k, |b, _| {
             b.iter(|| {
-                store.set(k.as_bytes(), k.as_bytes());
+                store.set(k.as_bytes(), k.as_bytes()).unwrap();
             })
         });
     }
@@ -0,0 +1,262 @@
+#[cfg(test)]
+mod test {
+    use crate::compaction::fifo::FifoCompaction;
+    use crate::compaction::leveled::LeveledCompaction;
+    use crate::compaction::strategy::CompactionStrategy;
+    use crate::compaction::tiered::SizeTieredCompaction;
+    use crate::sstable::sst::{create_sstable, SSTable, TableOptions};
+    use crate::sstable::value::{now_millis, Value};
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn table(dir: &Path, level: u16, number: u64, keys: &[&str]) -> SSTable {
+        let mut map = BTreeMap::new();
+        for key in keys {
+            map.insert(key.as_bytes().to_vec(), Value::Put(b"value".to_vec()));
+        }
+        let mut sstable = create_sstable(level, number, "test".to_owned(), dir);
+        sstable.write(&map, &TableOptions::default()).unwrap();
+        sstable
+    }
+
+    /// A table in L0 of roughly `entries` times 100 bytes.
+    fn sized(dir: &Path, number: u64, entries: usize) -> SSTable {
+        let mut map = BTreeMap::new();
+        for i in 0..entries {
+            map.insert(
+                format!("key{:06}", i).into_bytes(),
+                Value::Put(vec![b'v'; 100]),
+            );
+        }
+        let mut sstable = create_sstable(0, number, "test".to_owned(), dir);
+        sstable.write(&map, &TableOptions::default()).unwrap();
+        sstable
+    }
+
+    /// A table in L0 of `entries` times 100 bytes, written `age` ago.
+    fn aged(dir: &Path, number: u64, entries: usize, age: Duration) -> SSTable {
+        let sstable = create_sstable(0, number, "test".to_owned(), dir);
+        let mut builder = sstable.builder(&TableOptions::default()).unwrap();
+        for i in 0..entries {
+            let key = format!("key{:06}", i).into_bytes();
+            builder.add(&key, &Value::Put(vec![b'v'; 100])).unwrap();
+        }
+        builder.set_created_at(now_millis() - age.as_millis() as u64);
+        builder.finish().unwrap();
+        sstable
+    }
+
+    fn numbers(tables: &[SSTable]) -> Vec<u64> {
+        tables.iter().map(|sstable| sstable.get_number()).collect()
+    }
+
+    #[test]
+    fn test_level0_trigger() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let picker = LeveledCompaction::default();
+        let mut sstables = vec![
+            table(dir, 1, 1, &["a", "c"]),
+            table(dir, 1, 2, &["x", "z"]),
+            table(dir, 0, 3, &["b"]),
+            table(dir, 0, 4, &["a", "d"]),
+            table(dir, 0, 5, &["c"]),
+        ];
+        assert!(picker.pick(&sstables).unwrap().is_none());
+
+        sstables.push(table(dir, 0, 6, &["b", "e"]));
+        let compaction = picker.pick(&sstables).unwrap().unwrap();
+        assert_eq!((compaction.level, compaction.output_level), (0, 1));
+        assert_eq!(numbers(&compaction.inputs), vec![1, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_level_over_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let picker = LeveledCompaction {
+            max_bytes_for_level_base: 1,
+            level_size_multiplier: u64::MAX,
+            num_levels: 3,
+            ..Default::default()
+        };
+        let sstables = vec![
+            table(dir, 2, 1, &["b", "e"]),
+            table(dir, 2, 2, &["g", "h"]),
+            table(dir, 1, 5, &["a", "c"]),
+            table(dir, 1, 3, &["d", "f"]),
+        ];
+        let compaction = picker.pick(&sstables).unwrap().unwrap();
+        assert_eq!((compaction.level, compaction.output_level), (1, 2));
+        assert_eq!(numbers(&compaction.inputs), vec![1, 3]);
+
+        // The last level has no target.
+        let sstables = vec![table(dir, 2, 7, &["a", "z"])];
+        assert!(picker.pick(&sstables).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_level_pending_bytes() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let picker = LeveledCompaction {
+            level0_file_num_trigger: 2,
+            max_bytes_for_level_base: 1,
+            num_levels: 3,
+            ..Default::default()
+        };
+        let mut sstables = vec![table(dir, 2, 1, &["a", "z"]), table(dir, 0, 2, &["b"])];
+        assert_eq!(picker.pending_bytes(&sstables).unwrap(), 0);
+
+        sstables.push(table(dir, 0, 3, &["c"]));
+        let level0 = sstables[1].file_size().unwrap() + sstables[2].file_size().unwrap();
+        assert_eq!(picker.pending_bytes(&sstables).unwrap(), level0);
+
+        sstables.push(table(dir, 1, 4, &["d"]));
+        let level1 = sstables[3].file_size().unwrap() - 1;
+        assert_eq!(picker.pending_bytes(&sstables).unwrap(), level0 + level1);
+    }
+
+    #[test]
+    fn test_level_targets() {
+        let picker = LeveledCompaction {
+            max_bytes_for_level_base: 1000,
+            level_size_multiplier: 10,
+            ..Default::default()
+        };
+        assert_eq!(picker.max_bytes_for_level(1), 1000);
+        assert_eq!(picker.max_bytes_for_level(2), 10_000);
+        assert_eq!(picker.max_bytes_for_level(4), 1_000_000);
+    }
+
+    #[test]
+    fn test_tiered_merges_similar_tables() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let picker = SizeTieredCompaction::default();
+        let mut sstables = vec![
+            sized(dir, 1, 400),
+            sized(dir, 2, 100),
+            sized(dir, 3, 110),
+            sized(dir, 4, 90),
+        ];
+        assert!(picker.pick(&sstables).unwrap().is_none());
+
+        sstables.push(sized(dir, 5, 100));
+        let compaction = picker.pick(&sstables).unwrap().unwrap();
+        assert_eq!((compaction.level, compaction.output_level), (0, 0));
+        assert_eq!(numbers(&compaction.inputs), vec![2, 3, 4, 5]);
+        assert_eq!(compaction.target_file_size, u64::MAX);
+    }
+
+    #[test]
+    fn test_tiered_prefers_smallest_tables() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let picker = SizeTieredCompaction {
+            min_merge_width: 2,
+            max_merge_width: 3,
+            ..Default::default()
+        };
+        let sstables = vec![
+            table(dir, 1, 1, &["a", "z"]),
+            sized(dir, 2, 400),
+            sized(dir, 3, 400),
+            sized(dir, 4, 400),
+            sized(dir, 5, 400),
+            sized(dir, 6, 10),
+            sized(dir, 7, 10),
+        ];
+        let compaction = picker.pick(&sstables).unwrap().unwrap();
+        assert_eq!(numbers(&compaction.inputs), vec![6, 7]);
+
+        // Runs are contiguous and capped at the maximum width.
+        let compaction = picker.pick(&sstables[..6]).unwrap().unwrap();
+        assert_eq!(numbers(&compaction.inputs), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_tiered_skips_dissimilar_tables() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let picker = SizeTieredCompaction {
+            min_merge_width: 2,
+            ..Default::default()
+        };
+        let sstables = vec![sized(dir, 1, 10), sized(dir, 2, 100), sized(dir, 3, 10)];
+        assert!(picker.pick(&sstables).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_fifo_drops_oldest_over_budget() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let sstables = vec![
+            sized(dir, 1, 100),
+            sized(dir, 2, 100),
+            sized(dir, 3, 100),
+            sized(dir, 4, 100),
+        ];
+        let size = sstables[0].file_size().unwrap();
+        let picker = FifoCompaction {
+            max_table_files_size: 4 * size,
+            ..Default::default()
+        };
+        assert!(picker.pick(&sstables).unwrap().is_none());
+
+        let picker = FifoCompaction {
+            max_table_files_size: 2 * size + 1,
+            ..Default::default()
+        };
+        let compaction = picker.pick(&sstables).unwrap().unwrap();
+        assert!(compaction.drop_inputs);
+        assert_eq!(numbers(&compaction.inputs), vec![1, 2]);
+        assert_eq!(picker.pending_bytes(&sstables).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_fifo_drops_tables_past_ttl() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let hour = Duration::from_secs(3600);
+        let sstables = vec![
+            aged(dir, 1, 10, 3 * hour),
+            aged(dir, 2, 10, 2 * hour),
+            aged(dir, 3, 10, hour / 2),
+            aged(dir, 4, 10, Duration::ZERO),
+        ];
+        let picker = FifoCompaction {
+            ttl: Some(hour),
+            ..Default::default()
+        };
+        let compaction = picker.pick(&sstables).unwrap().unwrap();
+        assert_eq!(numbers(&compaction.inputs), vec![1, 2]);
+
+        let picker = FifoCompaction {
+            ttl: Some(4 * hour),
+            ..Default::default()
+        };
+        assert!(picker.pick(&sstables).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_level0_inputs_in_flush_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        // Table 9 was merged within L0 from flushes older than table 5.
+        let mut flushed = vec![];
+        for (number, flush_number) in [(9, 3), (5, 5), (7, 6), (8, 8)] {
+            let sstable = create_sstable(0, number, "test".to_owned(), dir);
+            let mut builder = sstable.builder(&TableOptions::default()).unwrap();
+            builder.add(b"key", &Value::Put(b"value".to_vec())).unwrap();
+            builder.set_flush_number(flush_number);
+            builder.finish().unwrap();
+            flushed.push(sstable);
+        }
+        let picker = LeveledCompaction::default();
+        let compaction = picker.pick(&flushed).unwrap().unwrap();
+        assert_eq!(numbers(&compaction.inputs), vec![9, 5, 7, 8]);
+    }
+}
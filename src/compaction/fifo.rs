@@ -0,0 +1,91 @@
+use std::io::Result;
+use std::time::Duration;
+
+use crate::compaction::strategy::{Compaction, CompactionStrategy};
+use crate::sstable::sst::SSTable;
+use crate::sstable::value::now_millis;
+
+/**
+ * Never merges tables, deletes the oldest ones instead.
+ *
+ * Flushed tables pile up in L0, and the oldest are dropped whole once all
+ * tables together take more than `max_table_files_size` bytes, or once
+ * they are older than `ttl`:
+ *
+ * before: |t1| |t2| |t3| |t4| |t5|    <- newest right, over the budget
+ * after:            |t3| |t4| |t5|
+ *
+ * Keys are written once and never rewritten, which suits data that is
+ * only kept for a while, such as metrics or a cache. Every key of a
+ * dropped table is gone, even one that was not overwritten since, so
+ * reads may find nothing for keys that were never deleted.
+ *
+ * Tables are never merged, so lookups read more of them the larger the
+ * budget is. Writes are not held back for the number of tables in L0,
+ * only `WriteStallOptions::immutable_memtables_slowdown` applies.
+ * `KVStore::compact_range` still merges the tables it covers.
+ */
+#[derive(Clone, Debug)]
+pub struct FifoCompaction {
+    /// Tables are dropped, oldest first, while all of them together take
+    /// more bytes than this.
+    pub max_table_files_size: u64,
+    /// Tables are dropped once they are older than this. `None` keeps them
+    /// while they fit the budget.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for FifoCompaction {
+    fn default() -> FifoCompaction {
+        FifoCompaction {
+            max_table_files_size: 1 << 30,
+            ttl: None,
+        }
+    }
+}
+
+impl CompactionStrategy for FifoCompaction {
+    /// Drop the oldest tables that are over the budget or past the TTL, `None` if there are none.
+    fn pick(&self, sstables: &[SSTable]) -> Result<Option<Compaction>> {
+        let mut size = 0;
+        for sstable in sstables {
+            size += sstable.file_size()?;
+        }
+        let expired_before = self.ttl.map(|ttl| {
+            let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+            now_millis().saturating_sub(ttl)
+        });
+        let mut inputs = vec![];
+        for sstable in sstables {
+            let expired = match expired_before {
+                Some(before) => sstable.created_at()? < before,
+                None => false,
+            };
+            if size <= self.max_table_files_size && !expired {
+                break;
+            }
+            size -= sstable.file_size()?;
+            inputs.push(sstable.clone());
+        }
+        if inputs.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Compaction {
+            level: inputs.iter().map(|t| t.get_level()).min().unwrap_or(0),
+            output_level: 0,
+            inputs,
+            target_file_size: u64::MAX,
+            drop_inputs: true,
+        }))
+    }
+
+    /// Dropping tables is quick, writes are never held back for it.
+    fn pending_bytes(&self, _sstables: &[SSTable]) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// L0 holds every table the budget allows, however many that is.
+    fn stalls_on_level0(&self) -> bool {
+        false
+    }
+}
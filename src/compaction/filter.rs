@@ -0,0 +1,46 @@
+/// What a `CompactionFilter` does with an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Keep,
+    /// Delete the key, as if `KVStore::delete` was called when the entry was written.
+    Remove,
+    /// Write this value for the key instead.
+    ChangeValue(Vec<u8>),
+}
+
+/**
+ * Custom logic compactions run over the entries they write.
+ *
+ * Each live key a compaction writes is passed once with its most recent
+ * value, so an expired session can be removed, or a value rewritten
+ * without a deprecated field, as the store gets to it:
+ *
+ * inputs: |a=1 b=2|  |b=3 c=4|
+ * merged: a=1 b=3 c=4  -> filter(a, 1), filter(b, 3), filter(c, 4)
+ *
+ * A key is only filtered when a compaction picks one of its tables, which
+ * may be long after it was written, and reads can see it until then. Use
+ * `KVStore::compact_range` to have a range filtered right away. Values
+ * kept in blob files are read for the filter, and a changed value is
+ * written to the sstable. Deleted keys and the memtable are not filtered.
+ *
+ * # Example
+ * ```
+ * use rkv::store::options::{CompactionFilter, Decision};
+ *
+ * struct DropSessions;
+ *
+ * impl CompactionFilter for DropSessions {
+ *     fn filter(&self, _level: u16, key: &[u8], _value: &[u8]) -> Decision {
+ *         match key.starts_with(b"session/") {
+ *             true => Decision::Remove,
+ *             false => Decision::Keep,
+ *         }
+ *     }
+ * }
+ * ```
+ */
+pub trait CompactionFilter: Send + Sync {
+    /// Decide on `key` and its `value`, on their way to a table at `level`.
+    fn filter(&self, level: u16, key: &[u8], value: &[u8]) -> Decision;
+}
@@ -0,0 +1,163 @@
+use std::io::Result;
+
+use crate::compaction::strategy::{Compaction, CompactionStrategy};
+use crate::sstable::sst::SSTable;
+
+/**
+ * Picks compactions that keep the store in levels.
+ *
+ * L0 holds the tables flushed from the memtable, their key ranges may
+ * overlap. In every other level the key ranges are disjoint, so a lookup
+ * reads at most one table per level, and each level targets
+ * `level_size_multiplier` times the size of the one above:
+ *
+ * L0: |a-z|  |c-q|  |b-f|                  <- flushed, count bounded
+ * L1: |a-d|e-k|l-r|s-z|                    <- max_bytes_for_level_base
+ * L2: |a-b|c-d|e-g|h-k|l-n|o-r|s-u|v-z|    <- 10x L1
+ *
+ * A compaction takes a table from the level most over its target, plus the
+ * tables of the next level its keys overlap, and writes the merged result
+ * to the next level. For L0 every table is taken, as they overlap each
+ * other. Keys of a level are always more recent than those of the levels
+ * below it.
+ *
+ * This is the default strategy of a store. It keeps reads and space
+ * overhead low, at the cost of rewriting keys once per level.
+ */
+#[derive(Clone, Debug)]
+pub struct LeveledCompaction {
+    /// Number of tables in L0, where flushed tables go, that starts a
+    /// compaction into L1.
+    pub level0_file_num_trigger: usize,
+    /// Size target of L1, in bytes.
+    pub max_bytes_for_level_base: u64,
+    /// Each level below L1 targets this many times the size of the one above.
+    pub level_size_multiplier: u64,
+    /// Compactions start a new table once the one they write reaches this many bytes.
+    pub target_file_size: u64,
+    /// Number of levels, L0 included. The last level has no size target.
+    pub num_levels: u16,
+}
+
+impl Default for LeveledCompaction {
+    fn default() -> LeveledCompaction {
+        LeveledCompaction {
+            level0_file_num_trigger: 4,
+            max_bytes_for_level_base: 10 << 20,
+            level_size_multiplier: 10,
+            target_file_size: 2 << 20,
+            num_levels: 7,
+        }
+    }
+}
+
+impl LeveledCompaction {
+    /// Size target of `level`, in bytes. L0 is bounded by its number of tables instead.
+    pub fn max_bytes_for_level(&self, level: u16) -> u64 {
+        let mut max_bytes = self.max_bytes_for_level_base;
+        for _ in 1..level {
+            max_bytes = max_bytes.saturating_mul(self.level_size_multiplier);
+        }
+        max_bytes
+    }
+
+    /// How far over its target `level` is, a compaction is due from `1.0` up.
+    fn score(&self, level: u16, tables: &[&SSTable]) -> Result<f64> {
+        if level == 0 {
+            return Ok(tables.len() as f64 / self.level0_file_num_trigger.max(1) as f64);
+        }
+        let mut size = 0;
+        for sstable in tables {
+            size += sstable.file_size()?;
+        }
+        Ok(size as f64 / self.max_bytes_for_level(level).max(1) as f64)
+    }
+}
+
+impl CompactionStrategy for LeveledCompaction {
+    /// Compact the level most over its target, `None` when every level is within its target.
+    fn pick(&self, sstables: &[SSTable]) -> Result<Option<Compaction>> {
+        let last_level = self.num_levels.max(2) - 1;
+        let mut picked: Option<(f64, u16)> = None;
+        for level in 0..last_level {
+            let tables = tables_at(sstables, level);
+            let score = self.score(level, &tables)?;
+            if score >= 1.0 && picked.is_none_or(|(best, _)| score > best) {
+                picked = Some((score, level));
+            }
+        }
+        let level = match picked {
+            Some((_, level)) => level,
+            None => return Ok(None),
+        };
+
+        let mut tables = tables_at(sstables, level);
+        tables.sort_by_cached_key(|sstable| sstable.recency());
+        if level > 0 {
+            tables.truncate(1);
+        }
+        let mut range: Option<(Vec<u8>, Vec<u8>)> = None;
+        for sstable in &tables {
+            if let Some((smallest, largest)) = sstable.key_range()? {
+                range = Some(match range {
+                    Some((low, high)) => (low.min(smallest), high.max(largest)),
+                    None => (smallest, largest),
+                });
+            }
+        }
+        let mut inputs = vec![];
+        if let Some((low, high)) = range {
+            for sstable in tables_at(sstables, level + 1) {
+                if let Some((smallest, largest)) = sstable.key_range()? {
+                    if smallest <= high && low <= largest {
+                        inputs.push(sstable.clone());
+                    }
+                }
+            }
+        }
+        inputs.extend(tables.into_iter().cloned());
+        Ok(Some(Compaction {
+            level,
+            output_level: level + 1,
+            inputs,
+            target_file_size: self.target_file_size,
+            drop_inputs: false,
+        }))
+    }
+
+    /// Merge `inputs` into the last level.
+    fn compact_range(&self, inputs: Vec<SSTable>) -> Compaction {
+        Compaction {
+            level: inputs.iter().map(|t| t.get_level()).min().unwrap_or(0),
+            output_level: self.num_levels.max(2) - 1,
+            inputs,
+            target_file_size: self.target_file_size,
+            drop_inputs: false,
+        }
+    }
+
+    /// All of L0 once it reaches its trigger, and how far each other level is over its target.
+    fn pending_bytes(&self, sstables: &[SSTable]) -> Result<u64> {
+        let mut bytes = 0;
+        for level in 0..self.num_levels.max(2) - 1 {
+            let tables = tables_at(sstables, level);
+            let mut size = 0;
+            for sstable in &tables {
+                size += sstable.file_size()?;
+            }
+            bytes += match level {
+                0 if tables.len() >= self.level0_file_num_trigger => size,
+                0 => 0,
+                _ => size.saturating_sub(self.max_bytes_for_level(level)),
+            };
+        }
+        Ok(bytes)
+    }
+}
+
+fn tables_at(sstables: &[SSTable], level: u16) -> Vec<&SSTable> {
+    sstables
+        .iter()
+        .filter(|sstable| sstable.get_level() == level)
+        .collect()
+}
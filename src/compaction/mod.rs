@@ -0,0 +1,7 @@
+#[cfg(test)]
+mod compaction_test;
+pub mod fifo;
+pub mod filter;
+pub mod leveled;
+pub mod strategy;
+pub mod tiered;
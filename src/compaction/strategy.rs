@@ -0,0 +1,68 @@
+use std::io::Result;
+
+use crate::sstable::sst::SSTable;
+
+/// Tables to merge, and where the merged tables go.
+pub struct Compaction {
+    pub level: u16,
+    pub output_level: u16,
+    /// Oldest first, the order in which a key's most recent value is decided.
+    pub inputs: Vec<SSTable>,
+    /// A new output table is started once the one being written reaches this many bytes.
+    pub target_file_size: u64,
+    /// Delete the inputs instead of merging them, see `FifoCompaction`.
+    pub drop_inputs: bool,
+}
+
+/**
+ * Decides which tables a `KVStore` compacts, see `LeveledCompaction`,
+ * `SizeTieredCompaction` and `FifoCompaction`.
+ *
+ * The store asks for a compaction after every flush, and keeps running
+ * the ones it gets until `pick` returns `None`.
+ *
+ * Tables are handed over oldest first: levels from the deepest up, then
+ * L0 in the order of the flushes they hold. A compaction must keep that
+ * order meaningful: no table left out of a compaction may sit between
+ * two of its inputs while holding one of their keys.
+ */
+pub trait CompactionStrategy: Send + Sync {
+    fn pick(&self, sstables: &[SSTable]) -> Result<Option<Compaction>>;
+
+    /// Bytes left to compact before `sstables` are within the strategy's
+    /// targets, writes are held back when this grows, see `WriteStallOptions`.
+    ///
+    /// Defaults to the size of the inputs of the next compaction.
+    fn pending_bytes(&self, sstables: &[SSTable]) -> Result<u64> {
+        let mut bytes = 0;
+        if let Some(compaction) = self.pick(sstables)? {
+            for sstable in &compaction.inputs {
+                bytes += sstable.file_size()?;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Whether writes are held back once L0 has many tables, see
+    /// `WriteStallOptions::level0_slowdown_trigger`.
+    ///
+    /// Defaults to `true`. Strategies that never bring the number of tables
+    /// in L0 down by compacting them return `false`.
+    fn stalls_on_level0(&self) -> bool {
+        true
+    }
+
+    /// The compaction `KVStore::compact_range` runs over `inputs`, every table
+    /// holding keys of the range, oldest first.
+    ///
+    /// Defaults to merging them into one table in L0.
+    fn compact_range(&self, inputs: Vec<SSTable>) -> Compaction {
+        Compaction {
+            level: 0,
+            output_level: 0,
+            inputs,
+            target_file_size: u64::MAX,
+            drop_inputs: false,
+        }
+    }
+}
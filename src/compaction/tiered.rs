@@ -0,0 +1,98 @@
+use std::io::Result;
+
+use crate::compaction::strategy::{Compaction, CompactionStrategy};
+use crate::sstable::sst::SSTable;
+
+/**
+ * Picks compactions that merge tables of similar size.
+ *
+ * Every table stays in L0, ordered by how recent its keys are. A run of
+ * at least `min_merge_width` neighbouring tables whose sizes are all
+ * within `size_ratio` of the run's average is merged into one table,
+ * which takes the run's place:
+ *
+ * before: |  64M  | |16M| |16M| |15M| |17M| |4M| |4M|    <- newest right
+ * after:  |  64M  | |        64M        | |4M| |4M|
+ *
+ * Tables then form tiers of similar sizes, and a key is rewritten about
+ * once per tier instead of once per level, at the cost of more tables to
+ * read per lookup and more space held by overwritten keys. When several
+ * runs qualify the one with the smallest tables goes first.
+ *
+ * Only L0 is compacted. Tables a store left in deeper levels under
+ * `LeveledCompaction` are kept as they are, and stay older than any
+ * table in L0.
+ */
+#[derive(Clone, Debug)]
+pub struct SizeTieredCompaction {
+    /// Fewest tables merged at once.
+    pub min_merge_width: usize,
+    /// Most tables merged at once.
+    pub max_merge_width: usize,
+    /// A table is similar to a run when neither its size nor the run's
+    /// average size is more than this many times the other.
+    pub size_ratio: f64,
+}
+
+impl Default for SizeTieredCompaction {
+    fn default() -> SizeTieredCompaction {
+        SizeTieredCompaction {
+            min_merge_width: 4,
+            max_merge_width: 32,
+            size_ratio: 2.0,
+        }
+    }
+}
+
+impl SizeTieredCompaction {
+    fn similar(&self, size: f64, average: f64) -> bool {
+        size <= average * self.size_ratio && average <= size * self.size_ratio
+    }
+}
+
+impl CompactionStrategy for SizeTieredCompaction {
+    /// Merge the run of similar tables with the smallest average size, `None` when there is none.
+    fn pick(&self, sstables: &[SSTable]) -> Result<Option<Compaction>> {
+        let min_width = self.min_merge_width.max(2);
+        let max_width = self.max_merge_width.max(min_width);
+        let mut tables = vec![];
+        for sstable in sstables.iter().filter(|sstable| sstable.get_level() == 0) {
+            tables.push((sstable, sstable.file_size()? as f64));
+        }
+        // Longest run of similar tables starting at each table, the first found wins ties.
+        let mut picked: Option<(f64, usize, usize)> = None;
+        for start in 0..tables.len() {
+            let mut total = 0.0;
+            let mut end = start;
+            while end < tables.len() && end - start < max_width {
+                let size = tables[end].1;
+                let average = (total + size) / (end - start + 1) as f64;
+                if !tables[start..=end]
+                    .iter()
+                    .all(|(_, size)| self.similar(*size, average))
+                {
+                    break;
+                }
+                total += size;
+                end += 1;
+            }
+            let width = end - start;
+            if width >= min_width {
+                let average = total / width as f64;
+                if picked.is_none_or(|(best, _, _)| average < best) {
+                    picked = Some((average, start, end));
+                }
+            }
+        }
+        Ok(picked.map(|(_, start, end)| Compaction {
+            level: 0,
+            output_level: 0,
+            inputs: tables[start..end]
+                .iter()
+                .map(|(sstable, _)| (*sstable).clone())
+                .collect(),
+            target_file_size: u64::MAX,
+            drop_inputs: false,
+        }))
+    }
+}
@@ -1,3 +1,6 @@
+mod compaction;
+mod manifest;
 mod sstable;
 pub mod store;
 mod utils;
+mod wal;
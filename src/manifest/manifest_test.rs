@@ -0,0 +1,233 @@
+#[cfg(test)]
+mod test {
+    use crate::manifest::version_edit::{TableMeta, VersionEdit};
+    use crate::manifest::version_set::Manifest;
+    use crate::utils::fault_fs::FaultInjectionFs;
+    use crate::utils::fs::{Durability, SyncPolicy};
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_edit_roundtrip() {
+        let edit = VersionEdit {
+            log_number: Some(4),
+            next_file_number: Some(9),
+            new_tables: vec![
+                TableMeta {
+                    number: 7,
+                    level: 2,
+                },
+                TableMeta {
+                    number: 8,
+                    level: 1,
+                },
+            ],
+            deleted_tables: vec![3, 5],
+            new_blob_files: vec![6],
+            deleted_blob_files: vec![1, 2],
+        };
+        let decoded = VersionEdit::decode(&edit.encode().unwrap()).unwrap();
+        assert_eq!(decoded, edit);
+    }
+
+    #[test]
+    fn test_recover_committed_tables() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manifest = Manifest::open(temp_dir.path(), SyncPolicy::default()).unwrap();
+        assert!(manifest.tables().is_empty());
+        manifest
+            .log_and_apply(VersionEdit {
+                log_number: Some(2),
+                next_file_number: Some(3),
+                new_tables: vec![
+                    TableMeta {
+                        number: 1,
+                        level: 1,
+                    },
+                    TableMeta {
+                        number: 2,
+                        level: 1,
+                    },
+                ],
+                new_blob_files: vec![4, 5],
+                ..Default::default()
+            })
+            .unwrap();
+        manifest
+            .log_and_apply(VersionEdit {
+                next_file_number: Some(4),
+                new_tables: vec![TableMeta {
+                    number: 3,
+                    level: 2,
+                }],
+                deleted_tables: vec![1, 2],
+                new_blob_files: vec![6],
+                deleted_blob_files: vec![4],
+                ..Default::default()
+            })
+            .unwrap();
+        drop(manifest);
+
+        let manifest = Manifest::open(temp_dir.path(), SyncPolicy::default()).unwrap();
+        assert_eq!(
+            manifest.tables(),
+            vec![TableMeta {
+                number: 3,
+                level: 2
+            }]
+        );
+        assert_eq!(manifest.blob_files(), vec![5, 6]);
+        assert_eq!(manifest.log_number(), 2);
+        assert_eq!(manifest.next_file_number(), 4);
+
+        let current = fs::read_to_string(temp_dir.path().join("CURRENT")).unwrap();
+        assert_eq!(current, "MANIFEST-000002\n");
+        assert!(!temp_dir.path().join("MANIFEST-000001").exists());
+    }
+
+    #[test]
+    fn test_recover_after_partial_next_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let fs = Arc::new(FaultInjectionFs::default());
+        let sync = SyncPolicy::new(Durability::OnFlush, fs.clone());
+        let mut manifest = Manifest::open(dir, sync.clone()).unwrap();
+        manifest
+            .log_and_apply(VersionEdit {
+                next_file_number: Some(3),
+                new_tables: vec![TableMeta {
+                    number: 2,
+                    level: 1,
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+        drop(manifest);
+
+        // The next open writes MANIFEST-000002 but fails before CURRENT names it.
+        fs.fail_file_syncs(true);
+        assert!(Manifest::open(dir, sync.clone()).is_err());
+        fs.fail_file_syncs(false);
+        fs.crash(dir);
+        // What reached the disk of it: the start of a record.
+        fs::write(dir.join("MANIFEST-000002"), b"torn").unwrap();
+
+        for _ in 0..2 {
+            let manifest = Manifest::open(dir, sync.clone()).unwrap();
+            assert_eq!(
+                manifest.tables(),
+                vec![TableMeta {
+                    number: 2,
+                    level: 1
+                }]
+            );
+            assert_eq!(manifest.next_file_number(), 3);
+        }
+    }
+
+    /// A manifest in `dir` holding its snapshot and then an edit per table in `numbers`.
+    fn manifest_with_tables(dir: &Path, numbers: &[u64]) {
+        let mut manifest = Manifest::open(dir, SyncPolicy::default()).unwrap();
+        for number in numbers {
+            manifest
+                .log_and_apply(VersionEdit {
+                    next_file_number: Some(number + 1),
+                    new_tables: vec![TableMeta {
+                        number: *number,
+                        level: 0,
+                    }],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    /// Offset of the payload of record `index` in a log file.
+    fn record_payload(bytes: &[u8], index: usize) -> usize {
+        let mut offset = 0;
+        for _ in 0..index {
+            let len = u64::from_le_bytes(bytes[offset + 4..offset + 12].try_into().unwrap());
+            offset += 12 + len as usize;
+        }
+        offset + 12
+    }
+
+    #[test]
+    fn test_corrupt_edit_before_the_tail_fails_recovery() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        manifest_with_tables(dir, &[1, 2, 3]);
+
+        // Record 0 is the snapshot, flip a byte of the edit adding table 2.
+        let path = dir.join("MANIFEST-000001");
+        let mut bytes = fs::read(&path).unwrap();
+        let offset = record_payload(&bytes, 2);
+        bytes[offset] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        for _ in 0..2 {
+            let err = Manifest::open(dir, SyncPolicy::default()).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_torn_last_edit_is_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        manifest_with_tables(dir, &[1, 2]);
+
+        let path = dir.join("MANIFEST-000001");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&path, bytes).unwrap();
+
+        let manifest = Manifest::open(dir, SyncPolicy::default()).unwrap();
+        assert!(manifest.dropped_tail());
+        assert_eq!(
+            manifest.tables(),
+            vec![TableMeta {
+                number: 1,
+                level: 0
+            }]
+        );
+        drop(manifest);
+        let manifest = Manifest::open(dir, SyncPolicy::default()).unwrap();
+        assert!(!manifest.dropped_tail());
+    }
+
+    #[test]
+    fn test_failed_edit_refuses_later_edits() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let fs = Arc::new(FaultInjectionFs::default());
+        let sync = SyncPolicy::new(Durability::OnFlush, fs.clone());
+        let mut manifest = Manifest::open(dir, sync.clone()).unwrap();
+        let edit = |number: u64| VersionEdit {
+            next_file_number: Some(number + 1),
+            new_tables: vec![TableMeta { number, level: 0 }],
+            ..Default::default()
+        };
+
+        // The edit is written but not synced, the next open may still see it.
+        fs.fail_file_syncs(true);
+        assert!(manifest.log_and_apply(edit(1)).is_err());
+        fs.fail_file_syncs(false);
+        assert!(manifest.tables().is_empty());
+        assert!(manifest.log_and_apply(edit(2)).is_err());
+        assert!(manifest.tables().is_empty());
+        drop(manifest);
+
+        let manifest = Manifest::open(dir, sync).unwrap();
+        assert_eq!(
+            manifest.tables(),
+            vec![TableMeta {
+                number: 1,
+                level: 0
+            }]
+        );
+    }
+}
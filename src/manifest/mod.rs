@@ -0,0 +1,4 @@
+#[cfg(test)]
+mod manifest_test;
+pub mod version_edit;
+pub mod version_set;
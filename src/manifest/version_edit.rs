@@ -0,0 +1,98 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Error, ErrorKind, Result};
+
+static TAG_LOG_NUMBER: u8 = 1;
+static TAG_NEXT_FILE_NUMBER: u8 = 2;
+static TAG_NEW_TABLE: u8 = 3;
+static TAG_DELETED_TABLE: u8 = 4;
+static TAG_NEW_BLOB_FILE: u8 = 5;
+static TAG_DELETED_BLOB_FILE: u8 = 6;
+
+/// A sstable that is part of the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableMeta {
+    pub number: u64,
+    pub level: u16,
+}
+
+/**
+ * A change to the set of live sstables and blob files.
+ *
+ * Edits are appended to the MANIFEST as single log records, so everything
+ * in one edit is applied together or not at all. Each field is written
+ * behind a one byte tag:
+ *
+ * |tag|<- payload ->|tag|<- payload ->| ...
+ */
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// Write-ahead logs numbered below this are persisted in sstables.
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub new_tables: Vec<TableMeta>,
+    pub deleted_tables: Vec<u64>,
+    pub new_blob_files: Vec<u64>,
+    pub deleted_blob_files: Vec<u64>,
+}
+
+impl VersionEdit {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        if let Some(log_number) = self.log_number {
+            buf.write_u8(TAG_LOG_NUMBER)?;
+            buf.write_u64::<LittleEndian>(log_number)?;
+        }
+        if let Some(next_file_number) = self.next_file_number {
+            buf.write_u8(TAG_NEXT_FILE_NUMBER)?;
+            buf.write_u64::<LittleEndian>(next_file_number)?;
+        }
+        for table in &self.new_tables {
+            buf.write_u8(TAG_NEW_TABLE)?;
+            buf.write_u64::<LittleEndian>(table.number)?;
+            buf.write_u16::<LittleEndian>(table.level)?;
+        }
+        for number in &self.deleted_tables {
+            buf.write_u8(TAG_DELETED_TABLE)?;
+            buf.write_u64::<LittleEndian>(*number)?;
+        }
+        for number in &self.new_blob_files {
+            buf.write_u8(TAG_NEW_BLOB_FILE)?;
+            buf.write_u64::<LittleEndian>(*number)?;
+        }
+        for number in &self.deleted_blob_files {
+            buf.write_u8(TAG_DELETED_BLOB_FILE)?;
+            buf.write_u64::<LittleEndian>(*number)?;
+        }
+        Ok(buf)
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<VersionEdit> {
+        let mut edit = VersionEdit::default();
+        let mut cursor = Cursor::new(payload);
+        while (cursor.position() as usize) < payload.len() {
+            let tag = cursor.read_u8()?;
+            if tag == TAG_LOG_NUMBER {
+                edit.log_number = Some(cursor.read_u64::<LittleEndian>()?);
+            } else if tag == TAG_NEXT_FILE_NUMBER {
+                edit.next_file_number = Some(cursor.read_u64::<LittleEndian>()?);
+            } else if tag == TAG_NEW_TABLE {
+                let number = cursor.read_u64::<LittleEndian>()?;
+                let level = cursor.read_u16::<LittleEndian>()?;
+                edit.new_tables.push(TableMeta { number, level });
+            } else if tag == TAG_DELETED_TABLE {
+                edit.deleted_tables.push(cursor.read_u64::<LittleEndian>()?);
+            } else if tag == TAG_NEW_BLOB_FILE {
+                edit.new_blob_files.push(cursor.read_u64::<LittleEndian>()?);
+            } else if tag == TAG_DELETED_BLOB_FILE {
+                edit.deleted_blob_files
+                    .push(cursor.read_u64::<LittleEndian>()?);
+            } else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unknown manifest tag {}", tag),
+                ));
+            }
+        }
+        Ok(edit)
+    }
+}
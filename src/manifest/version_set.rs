@@ -0,0 +1,193 @@
+use crate::manifest::version_edit::{TableMeta, VersionEdit};
+use crate::utils::fs::SyncPolicy;
+use crate::wal::journal::{LogReader, LogWriter};
+use log::debug;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, create_dir_all, remove_file};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+static CURRENT: &str = "CURRENT";
+static MANIFEST: &str = "MANIFEST";
+
+/**
+ * The committed state of a store.
+ *
+ * The MANIFEST is a log of `VersionEdit`s. Replaying it from the start
+ * gives the set of live sstables and blob files, the oldest write-ahead log that still
+ * matters and the next free file number. `CURRENT` names the MANIFEST in
+ * use, and is only ever replaced by a rename so it never points at a
+ * partially written file.
+ *
+ * A flush or a compaction becomes visible once its edit is appended here.
+ * Files on disk that are not in the manifest are leftovers of work that
+ * never committed.
+ */
+pub struct Manifest {
+    dir: PathBuf,
+    number: u64,
+    writer: LogWriter,
+    sync: SyncPolicy,
+    tables: BTreeMap<u64, TableMeta>,
+    blob_files: BTreeSet<u64>,
+    log_number: u64,
+    next_file_number: u64,
+    /// Recovery skipped a torn edit at the end of the previous MANIFEST.
+    dropped_tail: bool,
+    /// A write or sync of an edit failed, whether it reached the disk is unknown.
+    failed: bool,
+}
+
+impl Manifest {
+    pub fn exists(dir: &Path) -> bool {
+        dir.join(CURRENT).exists()
+    }
+
+    /// Recover the manifest in `dir`, or start an empty one.
+    ///
+    /// The recovered state is written to a fresh MANIFEST as a single edit,
+    /// so the log does not grow across restarts. Only a torn last edit is
+    /// skipped, a corrupt edit anywhere else fails with `InvalidData`.
+    pub fn open(dir: &Path, sync: SyncPolicy) -> Result<Manifest> {
+        create_dir_all(dir)?;
+        let mut edits = vec![];
+        let mut number = 0;
+        let mut dropped_tail = false;
+        if Manifest::exists(dir) {
+            let current = fs::read_to_string(dir.join(CURRENT))?;
+            let name = current.trim_end();
+            number = parse_manifest_name(name).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("CURRENT points at an unexpected file {:?}", name),
+                )
+            })?;
+            debug!("Recovering from {}", name);
+            let mut reader = LogReader::open(&dir.join(name))?;
+            while let Some(payload) = reader.read_record()? {
+                edits.push(VersionEdit::decode(&payload)?);
+            }
+            dropped_tail = reader.dropped_tail();
+        }
+
+        // An open that failed before `set_current` may have left part of this file behind.
+        let writer = LogWriter::truncate(&manifest_path(dir, number + 1))?;
+        let mut manifest = Manifest {
+            dir: dir.to_path_buf(),
+            number: number + 1,
+            writer,
+            sync,
+            tables: BTreeMap::new(),
+            blob_files: BTreeSet::new(),
+            log_number: 0,
+            next_file_number: 1,
+            dropped_tail,
+            failed: false,
+        };
+        for edit in edits {
+            manifest.apply(edit);
+        }
+        manifest.writer.add_record(&manifest.snapshot().encode()?)?;
+        manifest.sync.on_flush(&manifest_path(dir, number + 1))?;
+        manifest.set_current()?;
+        if number > 0 {
+            remove_file(manifest_path(dir, number))?;
+            manifest.sync.on_dir_change(dir)?;
+        }
+        Ok(manifest)
+    }
+
+    fn set_current(&self) -> Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", CURRENT));
+        fs::write(&tmp, format!("{}-{:06}\n", MANIFEST, self.number))?;
+        self.sync.on_flush(&tmp)?;
+        self.sync.rename(&tmp, &self.dir.join(CURRENT))?;
+        self.sync.on_dir_change(&self.dir)
+    }
+
+    fn snapshot(&self) -> VersionEdit {
+        VersionEdit {
+            log_number: Some(self.log_number),
+            next_file_number: Some(self.next_file_number),
+            new_tables: self.tables(),
+            new_blob_files: self.blob_files(),
+            ..Default::default()
+        }
+    }
+
+    fn apply(&mut self, edit: VersionEdit) {
+        if let Some(log_number) = edit.log_number {
+            self.log_number = log_number;
+        }
+        if let Some(next_file_number) = edit.next_file_number {
+            self.next_file_number = next_file_number;
+        }
+        for table in edit.new_tables {
+            self.tables.insert(table.number, table);
+        }
+        for number in edit.deleted_tables {
+            self.tables.remove(&number);
+        }
+        self.blob_files.extend(edit.new_blob_files);
+        for number in edit.deleted_blob_files {
+            self.blob_files.remove(&number);
+        }
+    }
+
+    /// Commit an edit. Once this returns the edit survives a restart.
+    ///
+    /// An edit whose write or sync fails may still be replayed by the next
+    /// open, so every later edit is refused until the manifest is reopened.
+    pub fn log_and_apply(&mut self, edit: VersionEdit) -> Result<()> {
+        if self.failed {
+            return Err(Error::other(
+                "An earlier manifest write failed, the store must be reopened",
+            ));
+        }
+        let payload = edit.encode()?;
+        let written = self
+            .writer
+            .add_record(&payload)
+            .and_then(|_| self.sync.on_flush(&manifest_path(&self.dir, self.number)));
+        if let Err(e) = written {
+            self.failed = true;
+            return Err(e);
+        }
+        self.apply(edit);
+        Ok(())
+    }
+
+    /// Live sstables, oldest first.
+    pub fn tables(&self) -> Vec<TableMeta> {
+        self.tables.values().copied().collect()
+    }
+
+    /// Live blob files, oldest first.
+    pub fn blob_files(&self) -> Vec<u64> {
+        self.blob_files.iter().copied().collect()
+    }
+
+    pub fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    pub fn next_file_number(&self) -> u64 {
+        self.next_file_number
+    }
+
+    /// `true` if recovery skipped a torn edit at the end of the MANIFEST.
+    ///
+    /// The files that edit named may still be on disk, and their numbers
+    /// may be at or past `next_file_number`.
+    pub fn dropped_tail(&self) -> bool {
+        self.dropped_tail
+    }
+}
+
+fn manifest_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{}-{:06}", MANIFEST, number))
+}
+
+fn parse_manifest_name(name: &str) -> Option<u64> {
+    name.strip_prefix(MANIFEST)?.strip_prefix('-')?.parse().ok()
+}
@@ -0,0 +1,290 @@
+use crate::sstable::value::Value;
+use crate::utils::fs::SyncPolicy;
+use crate::utils::futil;
+use crate::utils::rate_limiter::{Priority, RateLimiter};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use glob::glob;
+use log::{debug, error};
+use std::collections::HashSet;
+use std::fs::{create_dir_all, remove_file, File};
+use std::io::{BufWriter, Cursor, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+static BLOB: &str = "blob";
+
+/// Settings for keeping large values out of the sstables.
+#[derive(Clone, Debug)]
+pub struct BlobOptions {
+    /// Values of at least this many bytes are moved to blob files when the
+    /// memtable is flushed, the sstables only keep a `BlobRef` to them.
+    /// `None` keeps every value in the sstables.
+    pub min_blob_size: Option<usize>,
+    /// Fraction of the blob files, oldest first, whose values are moved to
+    /// a new blob file when a compaction comes across them. A blob file is
+    /// removed once no sstable refers to it, so this is what reclaims files
+    /// that are mostly, but not entirely, overwritten.
+    pub gc_age_cutoff: f64,
+}
+
+impl Default for BlobOptions {
+    fn default() -> BlobOptions {
+        BlobOptions {
+            min_blob_size: None,
+            gc_age_cutoff: 0.25,
+        }
+    }
+}
+
+/// Where a value that was moved out of an sstable lives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlobRef {
+    /// Number of the blob file.
+    pub file: u64,
+    /// Offset of the record in the blob file.
+    pub offset: u64,
+    /// Length of the whole record.
+    pub size: u64,
+}
+
+impl BlobRef {
+    /// |<- file (varint) ->|<- offset (varint) ->|<- size (varint) ->|
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        futil::write_varint(buf, self.file)?;
+        futil::write_varint(buf, self.offset)?;
+        futil::write_varint(buf, self.size)
+    }
+
+    pub fn decode<R: Read>(reader: &mut R) -> Result<BlobRef> {
+        Ok(BlobRef {
+            file: futil::read_varint(reader)?,
+            offset: futil::read_varint(reader)?,
+            size: futil::read_varint(reader)?,
+        })
+    }
+}
+
+/// Blob files live next to the sstables, named `<number>.blob`.
+pub fn blob_path(data_dir: &Path, number: u64) -> PathBuf {
+    data_dir.join(format!("{:06}.{}", number, BLOB))
+}
+
+pub fn parse_blob_name(path: &Path) -> Option<u64> {
+    if path.extension()?.to_str()? != BLOB {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/**
+ * Appends values to a new blob file.
+ *
+ * A blob file is a run of records:
+ *
+ * |<- crc32c (4) ->|KL|<- key ->|VL|<- Value ->| ...
+ *
+ * Both lengths are varints and the checksum covers the rest of the record.
+ * The key is kept so that a reader can tell that a `BlobRef` leads to the
+ * value of the key it was found under.
+ */
+pub struct BlobWriter {
+    path: PathBuf,
+    number: u64,
+    writer: BufWriter<File>,
+    offset: u64,
+    rate_limiter: Option<(Arc<RateLimiter>, Priority)>,
+}
+
+impl BlobWriter {
+    pub fn create(data_dir: &Path, number: u64) -> Result<BlobWriter> {
+        create_dir_all(data_dir)?;
+        let path = blob_path(data_dir, number);
+        Ok(BlobWriter {
+            writer: BufWriter::new(File::create(&path)?),
+            path,
+            number,
+            offset: 0,
+            rate_limiter: None,
+        })
+    }
+
+    /// Take the bytes of every record from `limiter` before writing it.
+    pub fn set_rate_limiter(&mut self, limiter: Arc<RateLimiter>, priority: Priority) {
+        self.rate_limiter = Some((limiter, priority));
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<BlobRef> {
+        let mut record = vec![];
+        futil::set_key(&mut record, key.len(), key)?;
+        futil::set_value(&mut record, value.len(), value)?;
+        if let Some((limiter, priority)) = &self.rate_limiter {
+            limiter.request(record.len() as u64 + 4, *priority);
+        }
+        self.writer
+            .write_u32::<LittleEndian>(crc32c::crc32c(&record))?;
+        self.writer.write_all(&record)?;
+        let blob = BlobRef {
+            file: self.number,
+            offset: self.offset,
+            size: record.len() as u64 + 4,
+        };
+        self.offset += blob.size;
+        Ok(blob)
+    }
+
+    /// Make the file durable, as far as `sync` asks for. Returns its number.
+    pub fn finish(mut self, sync: &SyncPolicy) -> Result<u64> {
+        self.writer.flush()?;
+        sync.on_flush(&self.path)?;
+        Ok(self.number)
+    }
+}
+
+/// Reads the values `BlobRef`s point to.
+#[derive(Clone)]
+pub struct BlobReader {
+    data_dir: PathBuf,
+}
+
+impl BlobReader {
+    pub fn new(data_dir: PathBuf) -> BlobReader {
+        BlobReader { data_dir }
+    }
+
+    /// Read the value of `key` from the record at `blob`.
+    pub fn read(&self, key: &[u8], blob: &BlobRef) -> Result<Vec<u8>> {
+        let path = blob_path(&self.data_dir, blob.file);
+        let corrupted = |reason: String| {
+            futil::corruption(format!(
+                "Corrupted blob file {}: {}",
+                path.display(),
+                reason
+            ))
+        };
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::Start(blob.offset))?;
+        let mut record = vec![];
+        file.take(blob.size).read_to_end(&mut record)?;
+        if record.len() as u64 != blob.size || record.len() < 4 {
+            return Err(corrupted(format!(
+                "record at offset {} is truncated",
+                blob.offset
+            )));
+        }
+        let checksum = (&record[..4]).read_u32::<LittleEndian>()?;
+        if crc32c::crc32c(&record[4..]) != checksum {
+            return Err(corrupted(format!(
+                "checksum mismatch for the record at offset {}",
+                blob.offset
+            )));
+        }
+        let mut cursor = Cursor::new(&record[4..]);
+        let found = futil::get_key(&mut cursor).map_err(|e| corrupted(e.to_string()))?;
+        if found != key {
+            return Err(corrupted(format!(
+                "record at offset {} belongs to another key",
+                blob.offset
+            )));
+        }
+        futil::get_value(&mut cursor).map_err(|e| corrupted(e.to_string()))
+    }
+
+    /// The value a reader sees for `value` at `now`, `None` for a deleted or expired key.
+    pub fn resolve(&self, key: &[u8], value: Value, now: u64) -> Result<Option<Vec<u8>>> {
+        if value.is_deleted(now) {
+            return Ok(None);
+        }
+        match value {
+            Value::Put(value) | Value::Expiring { value, .. } => Ok(Some(value)),
+            Value::Blob(blob) => self.read(key, &blob).map(Some),
+            Value::Delete => Ok(None),
+        }
+    }
+}
+
+/**
+ * Blob garbage collection, done as part of a compaction.
+ *
+ * Values in blob files numbered below `relocate_below` are copied to a new
+ * blob file as the compaction writes their keys, so that once every table
+ * referring to an old blob file is compacted, the file can be removed.
+ * Values that were overwritten or deleted are not copied.
+ */
+pub struct BlobGc {
+    reader: BlobReader,
+    relocate_below: u64,
+    next_file_number: Arc<AtomicU64>,
+    writer: Option<BlobWriter>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl BlobGc {
+    pub fn new(data_dir: PathBuf, relocate_below: u64, next_file_number: Arc<AtomicU64>) -> BlobGc {
+        BlobGc {
+            reader: BlobReader::new(data_dir),
+            relocate_below,
+            next_file_number,
+            writer: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Take the bytes of the values moved from `limiter`, at `Priority::Low`.
+    pub fn set_rate_limiter(&mut self, limiter: Arc<RateLimiter>) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    pub fn reader(&self) -> &BlobReader {
+        &self.reader
+    }
+
+    /// The value to write for `key` instead of `value`, if it has to move.
+    pub fn relocate(&mut self, key: &[u8], value: &Value) -> Result<Option<Value>> {
+        let blob = match value {
+            Value::Blob(blob) if blob.file < self.relocate_below => blob,
+            _ => return Ok(None),
+        };
+        let contents = self.reader.read(key, blob)?;
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let number = self.next_file_number.fetch_add(1, Ordering::SeqCst);
+                let mut writer = BlobWriter::create(&self.reader.data_dir, number)?;
+                if let Some(limiter) = &self.rate_limiter {
+                    writer.set_rate_limiter(limiter.clone(), Priority::Low);
+                }
+                self.writer.insert(writer)
+            }
+        };
+        Ok(Some(Value::Blob(writer.add(key, &contents)?)))
+    }
+
+    /// Finish the blob file values were moved to, if any. Returns its number.
+    pub fn finish(self, sync: &SyncPolicy) -> Result<Option<u64>> {
+        match self.writer {
+            Some(writer) => writer.finish(sync).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Remove blob files in `data_dir` that are not `live`.
+///
+/// These are files from a flush or compaction that never committed.
+pub fn remove_obsolete_blob_files(data_dir: &Path, live: &HashSet<u64>) {
+    let glob_pattern = format!("{}/*", data_dir.display());
+    for path in glob(&glob_pattern)
+        .expect("Failed to read glob pattern")
+        .flatten()
+    {
+        if let Some(number) = parse_blob_name(&path) {
+            if !live.contains(&number) {
+                debug!("Removing obsolete file {}", path.display());
+                if let Err(e) = remove_file(&path) {
+                    error!("Failed deleting {} because {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
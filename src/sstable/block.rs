@@ -0,0 +1,75 @@
+use crate::sstable::constants::BLOCK_TRAILER_SIZE;
+use crate::sstable::value::Value;
+use crate::utils::futil;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{Cursor, Read, Result, Seek, SeekFrom, Write};
+
+/// Where a block lives in an sstable file. `size` does not include the trailer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockHandle {
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BlockHandle {
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        buf.write_u64::<LittleEndian>(self.offset)?;
+        buf.write_u64::<LittleEndian>(self.size)
+    }
+
+    pub fn decode<R: Read>(reader: &mut R) -> Result<BlockHandle> {
+        Ok(BlockHandle {
+            offset: reader.read_u64::<LittleEndian>()?,
+            size: reader.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+/**
+ * Every block is followed by a trailer:
+ *
+ * |<-- contents -->|<- crc32c (4) ->|
+ *
+ * The checksum covers the contents. Returns the number of bytes written.
+ */
+pub fn write_block<W: Write>(writer: &mut W, contents: &[u8]) -> Result<u64> {
+    writer.write_all(contents)?;
+    writer.write_u32::<LittleEndian>(crc32c::crc32c(contents))?;
+    Ok(contents.len() as u64 + BLOCK_TRAILER_SIZE)
+}
+
+/// Read the block at `handle` and check it against its trailer.
+pub fn read_block(file: &mut File, handle: BlockHandle) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(handle.offset))?;
+    let mut buf = vec![];
+    file.take(handle.size + BLOCK_TRAILER_SIZE)
+        .read_to_end(&mut buf)?;
+    if buf.len() as u64 != handle.size + BLOCK_TRAILER_SIZE {
+        return Err(futil::corruption(format!(
+            "block at offset {} is truncated",
+            handle.offset
+        )));
+    }
+    let checksum = (&buf[handle.size as usize..]).read_u32::<LittleEndian>()?;
+    buf.truncate(handle.size as usize);
+    if crc32c::crc32c(&buf) != checksum {
+        return Err(futil::corruption(format!(
+            "checksum mismatch for the block at offset {}",
+            handle.offset
+        )));
+    }
+    Ok(buf)
+}
+
+/// Decode the key-value pairs of a data block, in the order they were written.
+pub fn decode_entries(block: &[u8]) -> Result<Vec<(Vec<u8>, Value)>> {
+    let mut cursor = Cursor::new(block);
+    let mut entries = vec![];
+    while (cursor.position() as usize) < block.len() {
+        let key = futil::get_key(&mut cursor)?;
+        let value = Value::decode(&mut cursor)?;
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
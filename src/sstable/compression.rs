@@ -0,0 +1,71 @@
+use crate::utils::futil;
+use std::io::Result;
+
+/// Codec applied to the data blocks of an sstable.
+///
+/// The codec is recorded in the properties of each table, so tables written
+/// with different settings can be read side by side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Blocks are stored as they are.
+    #[default]
+    None,
+    Lz4,
+    /// Smallest output, slowest to write.
+    Zstd,
+    Snappy,
+}
+
+impl Compression {
+    pub fn tag(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+            Compression::Snappy => 3,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Result<Compression> {
+        match tag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            3 => Ok(Compression::Snappy),
+            _ => Err(futil::corruption(format!("unknown compression {}", tag))),
+        }
+    }
+
+    pub fn compress(&self, block: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(block.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(block)),
+            Compression::Zstd => zstd::bulk::compress(block, 0),
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(block)
+                .map_err(|e| e.into()),
+        }
+    }
+
+    /// Fails with an `InvalidData` error when `block` was not produced by this codec.
+    pub fn decompress(&self, block: Vec<u8>) -> Result<Vec<u8>> {
+        let decompressed = match self {
+            Compression::None => return Ok(block),
+            Compression::Lz4 => {
+                lz4_flex::decompress_size_prepended(&block).map_err(|e| e.to_string())
+            }
+            Compression::Zstd => {
+                zstd::stream::decode_all(block.as_slice()).map_err(|e| e.to_string())
+            }
+            Compression::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(&block)
+                .map_err(|e| e.to_string()),
+        };
+        decompressed.map_err(|reason| {
+            futil::corruption(format!(
+                "failed to decompress a {:?} block: {}",
+                self, reason
+            ))
+        })
+    }
+}
@@ -1,3 +1,4 @@
-pub static WORD: usize = 8;
-pub static TOMBSTONE: &[u8] = &[];
 pub static RKV: &str = "rkv";
+pub static BLOCK_TRAILER_SIZE: u64 = 4;
+pub static FOOTER_SIZE: u64 = 44;
+pub static MAGIC: u64 = 0x726b_7673_7374_0004;
@@ -0,0 +1,26 @@
+use crate::sstable::sst::{parse_sstable_name, SSTable};
+use glob::glob;
+use std::path::Path;
+
+/**
+ * Find the sstables in `data_dir`, oldest first.
+ *
+ * The level and recency of each table come from its file name.
+ */
+pub fn discover_sstables(data_dir: &Path) -> Vec<(u64, SSTable)> {
+    let mut sstables = vec![];
+    let glob_pattern = format!("{}/*", data_dir.display());
+    for entry in glob(&glob_pattern).expect("Failed to read glob pattern") {
+        let path = match entry {
+            Ok(path) => path,
+            Err(e) => panic!("Failed to read sstable directory entry because {}", e),
+        };
+        let (level, number) = match parse_sstable_name(&path) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        sstables.push((number, SSTable::new(path, level)));
+    }
+    sstables.sort_by_key(|(number, _)| *number);
+    sstables
+}
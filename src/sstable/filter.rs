@@ -0,0 +1,135 @@
+/**
+ * A bloom filter over the keys of an sstable.
+ *
+ * |<-- bit array -->|<- probes (1) ->|
+ *
+ * Each key sets `probes` bits, derived from a single hash by double
+ * hashing. A key whose bits are not all set was never added, a lookup for
+ * it can skip the table without reading a block.
+ */
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    probes: u8,
+}
+
+impl BloomFilter {
+    /// Build a filter for `hashes`, each produced by `bloom_hash`.
+    pub fn build(hashes: &[u32], bits_per_key: usize) -> BloomFilter {
+        // ln(2) * bits_per_key minimises the false positive rate.
+        let probes = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        let n_bits = (hashes.len() * bits_per_key).max(64);
+        let n_bytes = n_bits.div_ceil(8);
+        let n_bits = n_bytes * 8;
+        let mut bits = vec![0; n_bytes];
+        for hash in hashes {
+            let mut h = *hash;
+            let delta = h.rotate_right(17);
+            for _ in 0..probes {
+                let position = h as usize % n_bits;
+                bits[position / 8] |= 1 << (position % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        BloomFilter { bits, probes }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.bits.clone();
+        buf.push(self.probes);
+        buf
+    }
+
+    pub fn decode(block: &[u8]) -> Option<BloomFilter> {
+        let (probes, bits) = block.split_last()?;
+        Some(BloomFilter {
+            bits: bits.to_vec(),
+            probes: *probes,
+        })
+    }
+
+    /// `false` if `key` is definitely not in the table.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        let n_bits = self.bits.len() * 8;
+        if n_bits == 0 {
+            return true;
+        }
+        let mut h = bloom_hash(key);
+        let delta = h.rotate_right(17);
+        for _ in 0..self.probes {
+            let position = h as usize % n_bits;
+            if self.bits[position / 8] & (1 << (position % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+}
+
+/// Murmur-like hash of `key`. It is persisted with the filter, so it must never change.
+pub fn bloom_hash(key: &[u8]) -> u32 {
+    let m: u32 = 0xc6a4_a793;
+    let mut h: u32 = 0xbc9f_1d34 ^ (key.len() as u32).wrapping_mul(m);
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        let w = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        h = h.wrapping_add(w).wrapping_mul(m);
+        h ^= h >> 16;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, byte) in rest.iter().enumerate() {
+            h = h.wrapping_add((*byte as u32) << (8 * i));
+        }
+        h = h.wrapping_mul(m);
+        h ^= h >> 24;
+    }
+    h
+}
+
+/**
+ * Maps keys to the prefix they are grouped by, such as `tenant/` in
+ * `tenant/user/id`.
+ *
+ * When a store is given an extractor, the filter of every table it writes
+ * also holds the prefix of each key. A prefix scan can then skip tables
+ * whose filter rules the prefix out.
+ *
+ * The name is stored with each table. Tables written with another
+ * extractor are always read by prefix scans.
+ */
+pub trait PrefixExtractor: Send + Sync {
+    /// Identifies the extractor and its settings.
+    fn name(&self) -> String;
+
+    /// The prefix of `key`, `None` for keys outside of any group.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+/// The first `len` bytes of each key. Shorter keys have no prefix.
+pub struct FixedPrefix(pub usize);
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> String {
+        format!("rkv.FixedPrefix.{}", self.0)
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.0)
+    }
+}
+
+/// Everything up to and including the first `delimiter`, so `tenant/` for
+/// `tenant/user/id` with `/`. Keys without the delimiter have no prefix.
+pub struct DelimitedPrefix(pub u8);
+
+impl PrefixExtractor for DelimitedPrefix {
+    fn name(&self) -> String {
+        format!("rkv.DelimitedPrefix.{}", self.0)
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let end = key.iter().position(|byte| *byte == self.0)?;
+        Some(&key[..=end])
+    }
+}
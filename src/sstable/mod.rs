@@ -1,4 +1,11 @@
+pub mod blob;
+pub mod block;
+pub mod compression;
 pub mod constants;
+#[cfg(test)]
+pub mod discover;
+pub mod filter;
 pub mod sst;
 #[cfg(test)]
 mod sstable_test;
+pub mod value;
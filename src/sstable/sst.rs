@@ -1,294 +1,1020 @@
-use crate::sstable::constants::{RKV, TOMBSTONE};
+use crate::compaction::filter::{CompactionFilter, Decision};
+use crate::compaction::strategy::Compaction;
+use crate::sstable::blob::BlobGc;
+use crate::sstable::block::{decode_entries, read_block, write_block, BlockHandle};
+use crate::sstable::compression::Compression;
+use crate::sstable::constants::{FOOTER_SIZE, MAGIC, RKV};
+use crate::sstable::filter::{bloom_hash, BloomFilter, PrefixExtractor};
+use crate::sstable::value::{now_millis, Value};
+use crate::store::iterator::{KVIterator, MergingIterator};
+use crate::utils::fs::SyncPolicy;
 use crate::utils::futil;
-use log::error;
-use std::cmp::Ordering;
+use crate::utils::rate_limiter::{Priority, RateLimiter};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use glob::glob;
+use log::{debug, error};
+#[cfg(test)]
 use std::collections::BTreeMap;
-use std::fs::create_dir_all;
-use std::fs::{remove_file, File, OpenOptions};
-use std::io::{Result, Seek, SeekFrom, Write};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{create_dir_all, metadata};
+use std::fs::{remove_file, File};
+use std::io::{BufWriter, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use uuid::Uuid;
+use std::time::UNIX_EPOCH;
+
+static PROP_ENTRIES: u8 = 1;
+static PROP_SMALLEST_KEY: u8 = 2;
+static PROP_LARGEST_KEY: u8 = 3;
+static PROP_FILTER: u8 = 4;
+static PROP_COMPRESSION: u8 = 5;
+static PROP_PREFIX_EXTRACTOR: u8 = 6;
+static PROP_BLOB_FILES: u8 = 7;
+static PROP_FLUSH_NUMBER: u8 = 8;
+static PROP_CREATED_AT: u8 = 9;
+
+/// Settings for the sstables a store writes.
+#[derive(Clone)]
+pub struct TableOptions {
+    /// Key-value pairs are grouped into blocks of about this many bytes.
+    /// A block is the unit read from disk by a lookup.
+    pub block_size: usize,
+    /// Size of the bloom filter of a table, in bits per key. More bits make
+    /// lookups for missing keys less likely to read a block. `0` writes no filter.
+    pub bits_per_key: usize,
+    /// Codec for the data blocks of new tables.
+    pub compression: Compression,
+    /// Also add key prefixes to the bloom filters, see `PrefixExtractor`.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+}
+
+impl Default for TableOptions {
+    fn default() -> TableOptions {
+        TableOptions {
+            block_size: 4096,
+            bits_per_key: 10,
+            compression: Compression::None,
+            prefix_extractor: None,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct SSTable {
-    dat: PathBuf,
-    index: PathBuf,
+    path: PathBuf,
     level: u16,
-    read: bool,
-    write: bool,
-    create: bool,
+    number: u64,
+    /// Index and properties, read from disk by the first lookup.
+    cached: Arc<Mutex<Option<Arc<TableIndex>>>>,
+    /// Deleted once the last handle to the table is dropped, see `delete_when_unused`.
+    obsolete: Arc<Mutex<Option<Arc<ObsoleteFiles>>>>,
 }
 
 impl SSTable {
     /**
      * The anatomy of an SSTable:
      *
-     * |0|0|0|0|0|0|0|9|t|e|s|t|_|m|o|d|e|0|0|0|0|0|0|0|7|1|2|3|4|5|6|7|
-     * |<- Key length->|<-key contents-->|<- Val length->|<-- Value -->|
-     * |0|0|0|0|0|0|0|4|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_|_
-     * |<- Key length->| ...
+     * |<- data block ->| ... |<- filter block ->|<- index block ->|<- properties ->|<- footer ->|
+     *
+     * Each data block is a run of key-value pairs:
+     *
+     * |9|t|e|s|t|_|m|o|d|e|1|7|1|2|3|4|5|6|7|4|_|_|_|_|_|_|
+     * |KL|<-key contents->|T|VL|<-Value->|KL| ...
      *
      * Notice: the key `test_mode` is 9 characters long. That's what the
      * `Key length` is trying to specify. The same explains the following
-     * `Val length`.
+     * `Val length`. Both lengths are varints, see `futil::write_varint`.
+     * `T` is the `ValueType` of the record, a deleted key is stored as
+     * `ValueType::Delete` without a value, a value set with a TTL as
+     * `ValueType::Expiring` with its expiry time. A large value may be
+     * kept in a blob file instead, stored here as a `ValueType::Blob` and
+     * the `BlobRef` to it. The properties list the blob files a table
+     * refers to.
+     *
+     * Data blocks are compressed with the codec named in the properties,
+     * checksums cover the compressed bytes.
+     *
+     * The index block has one entry per data block: the last key of the
+     * block and where to find it. A lookup binary searches the index, which
+     * is kept in memory, and reads the single data block that may hold the
+     * key. Every block ends with a checksum, see `write_block`.
+     *
+     * The filter block holds a bloom filter over all keys of the table. It
+     * is kept in memory along with the index, and rules out most lookups for
+     * keys the table does not have before any block is read.
      */
-    pub fn new(
-        filename: PathBuf,
-        level: u16,
-        read: bool,
-        write: bool,
-        create: bool,
-    ) -> Result<SSTable> {
-        Ok(SSTable {
-            dat: filename.clone(),
-            index: filename.with_extension("index"),
+    pub fn new(filename: PathBuf, level: u16) -> SSTable {
+        let number = parse_sstable_name(&filename).map_or(0, |(_, number)| number);
+        SSTable {
+            path: filename,
             level,
-            read,
-            write,
-            create,
-        })
+            number,
+            cached: Arc::new(Mutex::new(None)),
+            obsolete: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
     }
 
-    pub fn delete(&self) {
-        let filename = self.dat.clone();
-        let display_name = filename.as_path().display().to_string();
-        if let Err(e) = remove_file(filename) {
-            error!(
-                "Failed deleting the data file for {} because {}",
-                display_name, e
-            );
+    /// Delete `files` once every handle to this table is dropped.
+    ///
+    /// Lookups and iterators hold a handle, so they keep reading a table
+    /// that a compaction replaced until they are done with it.
+    pub fn delete_when_unused(&self, files: Arc<ObsoleteFiles>) {
+        *self.obsolete.lock().unwrap() = Some(files);
+    }
+
+    pub fn get_level(&self) -> u16 {
+        self.level
+    }
+
+    pub fn get_number(&self) -> u64 {
+        self.number
+    }
+
+    /// Attach the table's path to errors caused by bad bytes on disk.
+    fn corrupted(&self, e: Error) -> Error {
+        match e.kind() {
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => {
+                futil::corruption(format!("Corrupted sstable {}: {}", self.path.display(), e))
+            }
+            _ => e,
         }
-        if let Err(e) = remove_file(self.index.clone()) {
-            error!(
-                "Failed deleting the data file for {} because {}",
-                display_name, e
-            );
+    }
+
+    fn index(&self) -> Result<Arc<TableIndex>> {
+        let mut cached = self.cached.lock().unwrap();
+        if let Some(index) = cached.as_ref() {
+            return Ok(index.clone());
         }
+        let index = Arc::new(TableIndex::load(&self.path).map_err(|e| self.corrupted(e))?);
+        *cached = Some(index.clone());
+        Ok(index)
     }
 
-    fn open(&self) -> Result<(File, File)> {
-        let dat = OpenOptions::new()
-            .read(self.read)
-            .write(self.write)
-            .create(self.create)
-            .open(self.dat.clone())?;
+    fn read_entries(
+        &self,
+        file: &mut File,
+        handle: BlockHandle,
+        compression: Compression,
+    ) -> Result<Vec<(Vec<u8>, Value)>> {
+        read_block(file, handle)
+            .and_then(|block| compression.decompress(block))
+            .and_then(|block| decode_entries(&block))
+            .map_err(|e| self.corrupted(e))
+    }
 
-        let index = OpenOptions::new()
-            .read(self.read)
-            .write(self.write)
-            .create(self.create)
-            .open(self.index.clone())?;
+    pub fn builder(&self, options: &TableOptions) -> Result<TableBuilder> {
+        TableBuilder::create(&self.path, options.clone())
+    }
 
-        Ok((dat, index))
+    /// Write the key-value pairs of `map` as the contents of this table.
+    #[cfg(test)]
+    pub fn write(&mut self, map: &BTreeMap<Vec<u8>, Value>, options: &TableOptions) -> Result<()> {
+        let mut builder = self.builder(options)?;
+        for (key, value) in map {
+            builder.add(key, value)?;
+        }
+        builder.finish()
     }
 
-    pub fn get_level(&self) -> u16 {
-        self.level
+    /// Make a freshly written table durable, as far as `sync` asks for.
+    pub fn sync(&self, sync: &SyncPolicy) -> Result<()> {
+        sync.on_flush(&self.path)?;
+        match self.path.parent() {
+            Some(dir) => sync.on_dir_change(dir),
+            None => Ok(()),
+        }
     }
 
     /**
-     * Write a key-value pair to an SSTable.
+     * Search for the latest value of a given key in an SSTable.
+     *
+     * A deleted key is returned as `Value::Delete`, so callers can tell it
+     * apart from a key this table knows nothing about.
      *
-     * - Both key length and value length are 8 bytes long because
-     *   we are using u64 for keys and values.
-     * - Writing the key (and value) length helps us at the time of reading.
-     *   or else we would resort to delimiters and handle cases when the
-     *   delimiter character is also an input.
+     * Every block read along the way is checked against its checksum, a
+     * mismatch is returned as an `InvalidData` error.
      */
-    pub fn write(&mut self, map: &BTreeMap<Vec<u8>, Vec<u8>>) -> Result<()> {
-        let (mut data, mut index) = self.open()?;
-        data.seek(SeekFrom::End(0))?;
-        index.seek(SeekFrom::End(0))?;
+    pub fn search(&self, key: &[u8]) -> Result<Option<Value>> {
+        let index = self.index()?;
+        if !index.may_contain(key) {
+            return Ok(None);
+        }
+        let position = index
+            .blocks
+            .partition_point(|(last_key, _)| last_key.as_slice() < key);
+        let handle = match index.blocks.get(position) {
+            Some((_, handle)) => *handle,
+            None => return Ok(None),
+        };
+        let mut file = File::open(&self.path)?;
+        let entries = self.read_entries(&mut file, handle, index.compression)?;
+        Ok(entries
+            .into_iter()
+            .find(|(current_key, _)| current_key.as_slice() == key)
+            .map(|(_, value)| value))
+    }
 
-        for (key, value) in map {
-            let mut buf = vec![];
-            let seek_pos = data.stream_position()?;
-            futil::set_index(&mut index, seek_pos)?;
-            futil::set_key(&mut buf, key.len(), key)?;
-            futil::set_value(&mut buf, value.len(), value)?;
-            data.write_all(&buf)?;
+    /// The smallest and largest key of the table, `None` for an empty table.
+    pub fn key_range(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let index = self.index()?;
+        Ok(index.smallest.clone().zip(index.largest.clone()))
+    }
+
+    /// Size of the table file in bytes.
+    pub fn file_size(&self) -> Result<u64> {
+        Ok(metadata(&self.path)?.len())
+    }
+
+    /// Numbers of the blob files holding values of this table.
+    pub fn blob_files(&self) -> Result<Vec<u64>> {
+        Ok(self.index()?.blob_files.clone())
+    }
+
+    /// Number of the most recently flushed table whose keys this one holds.
+    ///
+    /// The table's own number unless it was written by a compaction, whose
+    /// output gets a new number but is only as recent as its inputs.
+    pub fn flush_number(&self) -> Result<u64> {
+        Ok(self.index()?.flush_number.unwrap_or(self.number))
+    }
+
+    /// Orders the tables of a level, larger values hold more recent writes.
+    ///
+    /// L0 tables go by `flush_number`, as compactions within L0 give their
+    /// outputs new numbers. A table whose properties can't be read goes by
+    /// its number, the lookup will report it.
+    pub fn recency(&self) -> u64 {
+        match self.level {
+            0 => self.flush_number().unwrap_or(self.number),
+            _ => self.number,
         }
+    }
 
-        Ok(())
+    /// When the table was written, in milliseconds since the Unix epoch.
+    ///
+    /// Tables written by a compaction are as old as their newest input.
+    /// Tables from before this was recorded go by the time their file was
+    /// last modified.
+    pub fn created_at(&self) -> Result<u64> {
+        if let Some(created_at) = self.index()?.created_at {
+            return Ok(created_at);
+        }
+        let modified = metadata(&self.path)?.modified()?;
+        Ok(modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64))
     }
 
-    /**
-     * Search for the latest value of a given key in an SSTable.
-     */
-    pub fn search(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let (mut data, mut index) = self.open()?;
-        let (mut start, mut end) = futil::get_index_range(&mut index);
-        while start < end {
-            let mid = start + (end - start) / 2;
-            let (current_key, value) = futil::key_value_at(mid, &mut index, &mut data)?;
-
-            match key.cmp(&current_key) {
-                Ordering::Less => {
-                    end = mid;
+    /// A cursor over the table, it reads one block at a time.
+    pub(crate) fn cursor(&self) -> Result<TableCursor> {
+        let index = self.index()?;
+        Ok(TableCursor {
+            sstable: self.clone(),
+            block: index.blocks.len(),
+            index,
+            file: File::open(&self.path)?,
+            entries: vec![],
+            position: None,
+        })
+    }
+
+    /// `false` if no key of the table has `prefix`, as extracted by `extractor`.
+    ///
+    /// Tables without prefixes from the same extractor in their filter may
+    /// hold any prefix.
+    pub fn may_contain_prefix(
+        &self,
+        prefix: &[u8],
+        extractor: &dyn PrefixExtractor,
+    ) -> Result<bool> {
+        let index = self.index()?;
+        if index.prefix_extractor != Some(extractor.name()) {
+            return Ok(true);
+        }
+        Ok(index.may_contain(prefix))
+    }
+
+    /// Iterate over the key-value pairs of the table in key order.
+    pub fn iter(&self) -> Result<TableIterator> {
+        Ok(TableIterator {
+            sstable: self.clone(),
+            index: self.index()?,
+            file: File::open(&self.path)?,
+            block: 0,
+            entries: vec![].into_iter(),
+        })
+    }
+
+    /// Iterate over the key-value pairs of the table, starting at the
+    /// first key not less than `start`.
+    pub fn iter_from(&self, start: &[u8]) -> Result<TableIterator> {
+        let mut iter = self.iter()?;
+        iter.block = iter
+            .index
+            .blocks
+            .partition_point(|(last_key, _)| last_key.as_slice() < start);
+        if let Some((_, handle)) = iter.index.blocks.get(iter.block) {
+            let entries = self.read_entries(&mut iter.file, *handle, iter.index.compression)?;
+            iter.entries = entries
+                .into_iter()
+                .filter(|(key, _)| key.as_slice() >= start)
+                .collect::<Vec<_>>()
+                .into_iter();
+            iter.block += 1;
+        }
+        Ok(iter)
+    }
+}
+
+/// Files no committed table needs anymore, removed when this is dropped.
+pub struct ObsoleteFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl ObsoleteFiles {
+    pub fn new(paths: Vec<PathBuf>) -> Arc<ObsoleteFiles> {
+        Arc::new(ObsoleteFiles { paths })
+    }
+}
+
+impl Drop for ObsoleteFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            debug!("Removing obsolete file {}", path.display());
+            if let Err(e) = remove_file(path) {
+                error!("Failed deleting {} because {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Streams key-value pairs into a new sstable file.
+pub struct TableBuilder {
+    writer: BufWriter<File>,
+    options: TableOptions,
+    offset: u64,
+    block: Vec<u8>,
+    index: Vec<u8>,
+    entries: u64,
+    smallest: Option<Vec<u8>>,
+    last_key: Vec<u8>,
+    /// `bloom_hash` of every key added.
+    key_hashes: Vec<u32>,
+    last_prefix: Option<Vec<u8>>,
+    blob_files: BTreeSet<u64>,
+    flush_number: Option<u64>,
+    created_at: Option<u64>,
+    rate_limiter: Option<(Arc<RateLimiter>, Priority)>,
+}
+
+impl TableBuilder {
+    fn create(path: &Path, options: TableOptions) -> Result<TableBuilder> {
+        Ok(TableBuilder {
+            writer: BufWriter::new(File::create(path)?),
+            options,
+            offset: 0,
+            block: vec![],
+            index: vec![],
+            entries: 0,
+            smallest: None,
+            last_key: vec![],
+            key_hashes: vec![],
+            last_prefix: None,
+            blob_files: BTreeSet::new(),
+            flush_number: None,
+            created_at: None,
+            rate_limiter: None,
+        })
+    }
+
+    /// Add a pair. Keys must be added in strictly increasing order.
+    pub fn add(&mut self, key: &[u8], value: &Value) -> Result<()> {
+        if self.entries > 0 && key <= self.last_key.as_slice() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "keys must be added to an sstable in increasing order",
+            ));
+        }
+        if self.smallest.is_none() {
+            self.smallest = Some(key.to_vec());
+        }
+        futil::set_key(&mut self.block, key.len(), key)?;
+        value.encode(&mut self.block)?;
+        if let Value::Blob(blob) = value {
+            self.blob_files.insert(blob.file);
+        }
+        self.last_key = key.to_vec();
+        self.entries += 1;
+        if self.options.bits_per_key > 0 {
+            self.key_hashes.push(bloom_hash(key));
+            if let Some(prefix) = self
+                .options
+                .prefix_extractor
+                .as_ref()
+                .and_then(|extractor| extractor.prefix(key))
+            {
+                // Keys arrive in order, so a prefix repeats on consecutive keys.
+                if self.last_prefix.as_deref() != Some(prefix) {
+                    self.key_hashes.push(bloom_hash(prefix));
+                    self.last_prefix = Some(prefix.to_vec());
                 }
-                Ordering::Equal => {
-                    if value == TOMBSTONE {
-                        return Ok(None);
-                    }
-                    if mid + 1 < end {
-                        let (next_key, _) = futil::key_value_at(mid + 1, &mut index, &mut data)?;
-                        if next_key != key {
-                            return Ok(Some(value));
-                        } else {
-                            start = mid + 1;
-                        }
-                    } else {
-                        return Ok(Some(value));
-                    }
+            }
+        }
+        if self.block.len() >= self.options.block_size {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let contents = self.options.compression.compress(&self.block)?;
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: contents.len() as u64,
+        };
+        self.offset += self.write_block(&contents)?;
+        self.block.clear();
+        futil::set_key(&mut self.index, self.last_key.len(), &self.last_key)?;
+        handle.encode(&mut self.index)
+    }
+
+    /// Record that the table holds keys up to flushed table `number`, see `SSTable::flush_number`.
+    pub fn set_flush_number(&mut self, number: u64) {
+        self.flush_number = Some(number);
+    }
+
+    /// Record that the newest writes the table holds are from `millis` since
+    /// the Unix epoch, see `SSTable::created_at`. Defaults to when it is finished.
+    pub fn set_created_at(&mut self, millis: u64) {
+        self.created_at = Some(millis);
+    }
+
+    /// Take the bytes of every block from `limiter` before writing it.
+    pub fn set_rate_limiter(&mut self, limiter: Arc<RateLimiter>, priority: Priority) {
+        self.rate_limiter = Some((limiter, priority));
+    }
+
+    fn write_block(&mut self, contents: &[u8]) -> Result<u64> {
+        if let Some((limiter, priority)) = &self.rate_limiter {
+            limiter.request(contents.len() as u64, *priority);
+        }
+        write_block(&mut self.writer, contents)
+    }
+
+    /// Bytes written so far, including the block being filled.
+    pub fn file_size(&self) -> u64 {
+        self.offset + self.block.len() as u64
+    }
+
+    /// Write the index, properties and footer. The table is readable once this returns.
+    pub fn finish(mut self) -> Result<()> {
+        self.flush_block()?;
+        let filter = if self.options.bits_per_key > 0 {
+            let contents = BloomFilter::build(&self.key_hashes, self.options.bits_per_key).encode();
+            let handle = BlockHandle {
+                offset: self.offset,
+                size: contents.len() as u64,
+            };
+            self.offset += self.write_block(&contents)?;
+            Some(handle)
+        } else {
+            None
+        };
+        let index = BlockHandle {
+            offset: self.offset,
+            size: self.index.len() as u64,
+        };
+        let contents = std::mem::take(&mut self.index);
+        self.offset += self.write_block(&contents)?;
+
+        let mut properties = vec![];
+        properties.write_u8(PROP_ENTRIES)?;
+        properties.write_u64::<LittleEndian>(self.entries)?;
+        if let Some(smallest) = &self.smallest {
+            properties.write_u8(PROP_SMALLEST_KEY)?;
+            futil::set_key(&mut properties, smallest.len(), smallest)?;
+            properties.write_u8(PROP_LARGEST_KEY)?;
+            futil::set_key(&mut properties, self.last_key.len(), &self.last_key)?;
+        }
+        if let Some(filter) = filter {
+            properties.write_u8(PROP_FILTER)?;
+            filter.encode(&mut properties)?;
+        }
+        properties.write_u8(PROP_COMPRESSION)?;
+        properties.write_u8(self.options.compression.tag())?;
+        if let (Some(_), Some(extractor)) = (filter, &self.options.prefix_extractor) {
+            let name = extractor.name();
+            properties.write_u8(PROP_PREFIX_EXTRACTOR)?;
+            futil::set_key(&mut properties, name.len(), name.as_bytes())?;
+        }
+        if !self.blob_files.is_empty() {
+            properties.write_u8(PROP_BLOB_FILES)?;
+            futil::write_varint(&mut properties, self.blob_files.len() as u64)?;
+            for number in &self.blob_files {
+                properties.write_u64::<LittleEndian>(*number)?;
+            }
+        }
+        if let Some(number) = self.flush_number {
+            properties.write_u8(PROP_FLUSH_NUMBER)?;
+            properties.write_u64::<LittleEndian>(number)?;
+        }
+        properties.write_u8(PROP_CREATED_AT)?;
+        properties.write_u64::<LittleEndian>(self.created_at.unwrap_or_else(now_millis))?;
+        let meta = BlockHandle {
+            offset: self.offset,
+            size: properties.len() as u64,
+        };
+        self.offset += self.write_block(&properties)?;
+
+        let footer = Footer { index, meta };
+        self.writer.write_all(&footer.encode()?)?;
+        self.writer.flush()
+    }
+}
+
+/**
+ * The last bytes of an sstable:
+ *
+ * |<- Index handle (16) ->|<- Properties handle (16) ->|<- crc32c (4) ->|<- Magic (8) ->|
+ *
+ * The checksum covers the two handles.
+ */
+struct Footer {
+    index: BlockHandle,
+    meta: BlockHandle,
+}
+
+impl Footer {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.index.encode(&mut buf)?;
+        self.meta.encode(&mut buf)?;
+        let footer_crc = crc32c::crc32c(&buf);
+        buf.write_u32::<LittleEndian>(footer_crc)?;
+        buf.write_u64::<LittleEndian>(MAGIC)?;
+        Ok(buf)
+    }
+
+    fn read(file: &mut File) -> Result<Footer> {
+        let file_len = file.metadata()?.len();
+        if file_len < FOOTER_SIZE {
+            return Err(futil::corruption("missing footer".to_owned()));
+        }
+        file.seek(SeekFrom::Start(file_len - FOOTER_SIZE))?;
+        let mut buf = vec![0; FOOTER_SIZE as usize];
+        file.read_exact(&mut buf)?;
+        let mut cursor = buf.as_slice();
+        let footer = Footer {
+            index: BlockHandle::decode(&mut cursor)?,
+            meta: BlockHandle::decode(&mut cursor)?,
+        };
+        let footer_crc = cursor.read_u32::<LittleEndian>()?;
+        if cursor.read_u64::<LittleEndian>()? != MAGIC {
+            return Err(futil::corruption("bad magic number".to_owned()));
+        }
+        if crc32c::crc32c(&buf[..32]) != footer_crc {
+            return Err(futil::corruption("footer checksum mismatch".to_owned()));
+        }
+        Ok(footer)
+    }
+}
+
+/// What a reader keeps in memory about a table.
+struct TableIndex {
+    /// Last key and location of every data block.
+    blocks: Vec<(Vec<u8>, BlockHandle)>,
+    entries: u64,
+    smallest: Option<Vec<u8>>,
+    largest: Option<Vec<u8>>,
+    filter: Option<BloomFilter>,
+    /// Codec of the data blocks.
+    compression: Compression,
+    /// Name of the extractor whose prefixes are in the filter.
+    prefix_extractor: Option<String>,
+    blob_files: Vec<u64>,
+    flush_number: Option<u64>,
+    created_at: Option<u64>,
+}
+
+impl TableIndex {
+    fn load(path: &Path) -> Result<TableIndex> {
+        let mut file = File::open(path)?;
+        let footer = Footer::read(&mut file)?;
+
+        let index_block = read_block(&mut file, footer.index)?;
+        let mut cursor = Cursor::new(index_block.as_slice());
+        let mut blocks = vec![];
+        while (cursor.position() as usize) < index_block.len() {
+            let last_key = futil::get_key(&mut cursor)?;
+            blocks.push((last_key, BlockHandle::decode(&mut cursor)?));
+        }
+
+        let mut table = TableIndex {
+            blocks,
+            entries: 0,
+            smallest: None,
+            largest: None,
+            filter: None,
+            compression: Compression::None,
+            prefix_extractor: None,
+            blob_files: vec![],
+            flush_number: None,
+            created_at: None,
+        };
+        let properties = read_block(&mut file, footer.meta)?;
+        let mut cursor = Cursor::new(properties.as_slice());
+        while (cursor.position() as usize) < properties.len() {
+            let tag = cursor.read_u8()?;
+            if tag == PROP_ENTRIES {
+                table.entries = cursor.read_u64::<LittleEndian>()?;
+            } else if tag == PROP_SMALLEST_KEY {
+                table.smallest = Some(futil::get_key(&mut cursor)?);
+            } else if tag == PROP_LARGEST_KEY {
+                table.largest = Some(futil::get_key(&mut cursor)?);
+            } else if tag == PROP_FILTER {
+                let filter = read_block(&mut file, BlockHandle::decode(&mut cursor)?)?;
+                match BloomFilter::decode(&filter) {
+                    Some(filter) => table.filter = Some(filter),
+                    None => return Err(futil::corruption("empty filter block".to_owned())),
                 }
-                Ordering::Greater => {
-                    start = mid + 1;
+            } else if tag == PROP_COMPRESSION {
+                table.compression = Compression::from_tag(cursor.read_u8()?)?;
+            } else if tag == PROP_PREFIX_EXTRACTOR {
+                let name = futil::get_key(&mut cursor)?;
+                table.prefix_extractor = Some(String::from_utf8_lossy(&name).into_owned());
+            } else if tag == PROP_BLOB_FILES {
+                for _ in 0..futil::read_varint(&mut cursor)? {
+                    table.blob_files.push(cursor.read_u64::<LittleEndian>()?);
                 }
+            } else if tag == PROP_FLUSH_NUMBER {
+                table.flush_number = Some(cursor.read_u64::<LittleEndian>()?);
+            } else if tag == PROP_CREATED_AT {
+                table.created_at = Some(cursor.read_u64::<LittleEndian>()?);
+            } else {
+                return Err(futil::corruption(format!("unknown property {}", tag)));
             }
         }
+        Ok(table)
+    }
 
-        Ok(None)
+    fn may_contain(&self, key: &[u8]) -> bool {
+        match &self.filter {
+            Some(filter) => filter.may_contain(key),
+            None => true,
+        }
     }
 }
 
-pub fn create_sstable(level: u16, name: String, sstable_dir: &Path) -> SSTable {
-    let uuid = Uuid::new_v4();
-    let this_level = level + 1;
-    let slug = format!("{}-{}.{}", this_level, uuid, RKV);
-    let dirname = sstable_dir.join(name).join(RKV).join("data");
-    create_dir_all(dirname.clone()).unwrap();
-    let filename = dirname.join(slug);
-    SSTable::new(filename, this_level, true, true, true).unwrap()
+/// Moves over the pairs of an sstable in either direction, keeping the block it is in.
+pub(crate) struct TableCursor {
+    sstable: SSTable,
+    index: Arc<TableIndex>,
+    file: File,
+    /// Index of the block in `entries`.
+    block: usize,
+    entries: Vec<(Vec<u8>, Value)>,
+    position: Option<usize>,
 }
 
-fn merge_two(
-    sstable_old: &SSTable,
-    sstable_new: &SSTable,
-    merged_sstable: &mut SSTable,
-    log_size: usize,
-) -> Result<()> {
-    let mut map: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
-    let (mut i, mut j) = (0, 0);
-
-    let (mut o_data, mut o_index) = sstable_old.open()?;
-    let (_, o_end) = futil::get_index_range(&mut o_index);
-
-    let (mut n_data, mut n_index) = sstable_new.open()?;
-    let (_, n_end) = futil::get_index_range(&mut n_index);
-
-    while i < o_end && j < n_end {
-        let (o_key, o_value) = futil::key_value_at(i, &mut o_index, &mut o_data)?;
-        let (n_key, n_value) = futil::key_value_at(j, &mut n_index, &mut n_data)?;
-
-        match o_key.cmp(&n_key) {
-            Ordering::Less => {
-                map.insert(o_key, o_value);
-                i += 1;
-            }
-            Ordering::Equal => {
-                map.insert(n_key, n_value);
-                i += 1;
-                j += 1;
+impl TableCursor {
+    fn load_block(&mut self, block: usize) -> Result<()> {
+        if block != self.block {
+            self.position = None;
+            let (_, handle) = self.index.blocks[block];
+            self.entries =
+                self.sstable
+                    .read_entries(&mut self.file, handle, self.index.compression)?;
+            self.block = block;
+        }
+        Ok(())
+    }
+
+    /// Place the cursor on the first entry of `block`, or past the last block.
+    fn start_of_block(&mut self, block: usize) -> Result<()> {
+        if block >= self.index.blocks.len() {
+            self.position = None;
+            return Ok(());
+        }
+        self.load_block(block)?;
+        self.position = Some(0);
+        Ok(())
+    }
+
+    /// Place the cursor on the last entry of `block`.
+    fn end_of_block(&mut self, block: usize) -> Result<()> {
+        self.load_block(block)?;
+        self.position = self.entries.len().checked_sub(1);
+        Ok(())
+    }
+}
+
+impl crate::store::cursor::Cursor for TableCursor {
+    fn valid(&self) -> bool {
+        self.position.is_some()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.entries[self.position.unwrap()].0
+    }
+
+    fn value(&self) -> &Value {
+        &self.entries[self.position.unwrap()].1
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let block = self
+            .index
+            .blocks
+            .partition_point(|(last_key, _)| last_key.as_slice() < key);
+        self.start_of_block(block)?;
+        if self.position.is_some() {
+            self.position = Some(self.entries.partition_point(|(k, _)| k.as_slice() < key));
+        }
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let block = self
+            .index
+            .blocks
+            .partition_point(|(last_key, _)| last_key.as_slice() < key);
+        if block < self.index.blocks.len() {
+            self.load_block(block)?;
+            let after = self.entries.partition_point(|(k, _)| k.as_slice() <= key);
+            if after > 0 {
+                self.position = Some(after - 1);
+                return Ok(());
             }
-            Ordering::Greater => {
-                map.insert(n_key, n_value);
-                j += 1;
+        }
+        // Every key of `block` is greater, the answer ends the block before.
+        match block.checked_sub(1) {
+            Some(block) => self.end_of_block(block),
+            None => {
+                self.position = None;
+                Ok(())
             }
         }
+    }
 
-        if map.len() > log_size {
-            merged_sstable.write(&map)?;
-            map.clear();
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.start_of_block(0)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        match self.index.blocks.len().checked_sub(1) {
+            Some(block) => self.end_of_block(block),
+            None => {
+                self.position = None;
+                Ok(())
+            }
         }
     }
 
-    while i < o_end {
-        let (o_key, o_value) = futil::key_value_at(i, &mut o_index, &mut o_data)?;
-        map.insert(o_key, o_value);
-        i += 1;
-        if map.len() > log_size {
-            merged_sstable.write(&map)?;
-            map.clear();
+    fn next(&mut self) -> Result<()> {
+        match self.position {
+            Some(position) if position + 1 < self.entries.len() => {
+                self.position = Some(position + 1);
+                Ok(())
+            }
+            Some(_) => self.start_of_block(self.block + 1),
+            None => Ok(()),
         }
     }
 
-    while j < n_end {
-        let (n_key, n_value) = futil::key_value_at(j, &mut n_index, &mut n_data)?;
-        map.insert(n_key, n_value);
-        j += 1;
-        if map.len() > log_size {
-            merged_sstable.write(&map)?;
-            map.clear();
+    fn prev(&mut self) -> Result<()> {
+        match self.position {
+            Some(position) if position > 0 => {
+                self.position = Some(position - 1);
+                Ok(())
+            }
+            Some(_) if self.block > 0 => self.end_of_block(self.block - 1),
+            _ => {
+                self.position = None;
+                Ok(())
+            }
         }
     }
+}
 
-    merged_sstable.write(&map)?;
-    Ok(())
+/// Key-value pairs of an sstable in key order, read one block at a time.
+pub struct TableIterator {
+    sstable: SSTable,
+    index: Arc<TableIndex>,
+    file: File,
+    block: usize,
+    entries: std::vec::IntoIter<(Vec<u8>, Value)>,
 }
 
-fn merge_sstables(
-    sstables: Vec<SSTable>,
-    name: String,
-    sstable_dir: &Path,
-    level: u16,
-) -> Vec<SSTable> {
-    let mut merged_sstables = Vec::new();
-    for pair in sstables.chunks(2) {
-        match pair.len() {
-            1 => {
-                let sstable = pair[0].clone();
-                merged_sstables.push(sstable);
+impl Iterator for TableIterator {
+    type Item = Result<(Vec<u8>, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
             }
-            2 => {
-                let sstable_old = pair[0].clone();
-                let sstable_new = pair[1].clone();
-                let mut merged_sstable = create_sstable(level, name.clone(), sstable_dir);
-                merge_two(&sstable_old, &sstable_new, &mut merged_sstable, 1000).unwrap();
-                sstable_old.delete();
-                sstable_new.delete();
-                merged_sstables.push(merged_sstable);
+            let (_, handle) = self.index.blocks.get(self.block)?;
+            self.block += 1;
+            match self
+                .sstable
+                .read_entries(&mut self.file, *handle, self.index.compression)
+            {
+                Ok(entries) => self.entries = entries.into_iter(),
+                Err(e) => {
+                    self.block = self.index.blocks.len();
+                    return Some(Err(e));
+                }
             }
-            _ => unreachable!("SSTable length should be 1 or 2"),
         }
     }
-    merged_sstables
 }
 
+/// Directory holding the sstables of the store called `name`.
+pub fn sstable_data_dir(sstable_dir: &Path, name: &str) -> PathBuf {
+    sstable_dir.join(name).join(RKV).join("data")
+}
+
+/**
+ * SSTables are named `<level>-<number>.rkv`.
+ *
+ * The number is handed out by the store in increasing order, so a table
+ * with a larger number holds more recent writes than one with a smaller
+ * number. Returns `(level, number)`.
+ */
+pub fn parse_sstable_name(path: &Path) -> Option<(u16, u64)> {
+    if path.extension()?.to_str()? != RKV {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let (level, number) = stem.split_once('-')?;
+    Some((level.parse().ok()?, number.parse().ok()?))
+}
+
+pub fn sstable_path(data_dir: &Path, level: u16, number: u64) -> PathBuf {
+    data_dir.join(format!("{}-{:06}.{}", level, number, RKV))
+}
+
+/// A new, still empty, sstable of the store called `name`.
+pub fn create_sstable(level: u16, number: u64, name: String, sstable_dir: &Path) -> SSTable {
+    let dirname = sstable_data_dir(sstable_dir, &name);
+    create_dir_all(dirname.clone()).unwrap();
+    let filename = sstable_path(&dirname, level, number);
+    SSTable::new(filename, level)
+}
+
+/**
+ * Merge the inputs of `compaction` (oldest first) into new tables at its
+ * output level.
+ *
+ * When several inputs hold a key, the most recent value is kept. A new
+ * table is started whenever the current one reaches the target file size,
+ * so that a later compaction can pick a slice of the level. Values `blobs`
+ * asks to move go to its blob file.
+ *
+ * A delete is only needed while an older value of its key may be left in
+ * a table that is not merged. `older` holds the key ranges of the tables
+ * that are older than the outputs, the inputs aside, and deletes of keys
+ * outside all of them are dropped:
+ *
+ * L1:       |a-f| + |d-k|         <- inputs, `e` deleted in |d-k|
+ * L2: |a-c|  |e-h|  |m-p|         <- older, `e` may still be in |e-h|
+ *
+ * Here the delete of `e` is kept. Once it reaches the last level nothing
+ * older is left, and it goes along with every value it was hiding.
+ *
+ * Values expired by `now` are treated like deletes. `filter` sees the
+ * most recent value of each live key before it is written, a key it
+ * removes is treated like a delete as well.
+ *
+ * Every table and blob block written takes its bytes from `rate_limiter`
+ * first, at `Priority::Low`.
+ *
+ * The input tables are left untouched: they stay live until the caller has
+ * committed the result to the manifest.
+ */
+#[allow(clippy::too_many_arguments)]
 pub fn sstable_compaction(
-    shared_sstables: Arc<Mutex<Vec<SSTable>>>,
-    name: String,
-    level: u16,
+    compaction: &Compaction,
+    older: &[(Vec<u8>, Vec<u8>)],
+    filter: Option<&dyn CompactionFilter>,
+    name: &str,
     sstable_dir: &Path,
-) -> Arc<Mutex<Vec<SSTable>>> {
-    let sstable_dir = Arc::new(sstable_dir.to_path_buf());
-    let this_level = Arc::new(Mutex::new(level));
-    let merged_sstables = thread::spawn(move || {
-        let mut sstables = shared_sstables.lock().unwrap();
-        while sstables.len() > 1 {
-            match this_level.lock() {
-                Ok(mut level) => {
-                    *level += 1;
-                    *sstables =
-                        merge_sstables(sstables.to_vec(), name.clone(), &sstable_dir, *level);
+    next_file_number: &AtomicU64,
+    sync: &SyncPolicy,
+    options: &TableOptions,
+    blobs: &mut BlobGc,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+    now: u64,
+) -> Result<Vec<SSTable>> {
+    let mut sources: Vec<KVIterator> = vec![];
+    let mut flush_number = 0;
+    let mut created_at = 0;
+    for sstable in &compaction.inputs {
+        sources.push(Box::new(sstable.iter()?));
+        flush_number = flush_number.max(sstable.flush_number()?);
+        created_at = created_at.max(sstable.created_at()?);
+    }
+    let mut outputs = vec![];
+    let mut current: Option<(SSTable, TableBuilder)> = None;
+    for entry in MergingIterator::new(sources) {
+        let (key, mut value) = entry?;
+        if value.is_deleted(now) {
+            value = Value::Delete;
+        }
+        if let Some(filter) = filter {
+            if let Some(contents) = blobs.reader().resolve(&key, value.clone(), now)? {
+                match filter.filter(compaction.output_level, &key, &contents) {
+                    Decision::Keep => {}
+                    Decision::Remove => value = Value::Delete,
+                    Decision::ChangeValue(changed) => {
+                        value = match value {
+                            Value::Expiring { expires_at, .. } => Value::Expiring {
+                                value: changed,
+                                expires_at,
+                            },
+                            _ => Value::Put(changed),
+                        }
+                    }
+                }
+            }
+        }
+        if value == Value::Delete
+            && !older
+                .iter()
+                .any(|(smallest, largest)| smallest <= &key && &key <= largest)
+        {
+            continue;
+        }
+        let (_, builder) = match &mut current {
+            Some(current) => current,
+            None => {
+                let number = next_file_number.fetch_add(1, AtomicOrdering::SeqCst);
+                let sstable = create_sstable(
+                    compaction.output_level,
+                    number,
+                    name.to_owned(),
+                    sstable_dir,
+                );
+                let mut builder = sstable.builder(options)?;
+                builder.set_flush_number(flush_number);
+                builder.set_created_at(created_at);
+                if let Some(limiter) = rate_limiter {
+                    builder.set_rate_limiter(limiter.clone(), Priority::Low);
                 }
-                Err(poisoned) => panic!("Poisoned lock: {:?}", poisoned),
+                current.insert((sstable, builder))
             }
+        };
+        match blobs.relocate(&key, &value)? {
+            Some(relocated) => builder.add(&key, &relocated)?,
+            None => builder.add(&key, &value)?,
         }
-        sstables.len();
-        sstables.to_vec()
-    })
-    .join()
-    .unwrap();
+        if builder.file_size() >= compaction.target_file_size {
+            if let Some((sstable, builder)) = current.take() {
+                builder.finish()?;
+                sstable.sync(sync)?;
+                outputs.push(sstable);
+            }
+        }
+    }
+    if let Some((sstable, builder)) = current {
+        builder.finish()?;
+        sstable.sync(sync)?;
+        outputs.push(sstable);
+    }
+    Ok(outputs)
+}
 
-    Arc::new(Mutex::new(merged_sstables))
+/// Remove sstable files in `data_dir` that are not `live`.
+///
+/// These are tables from a flush or compaction that never committed.
+pub fn remove_obsolete_sstables(data_dir: &Path, live: &HashSet<u64>) {
+    let glob_pattern = format!("{}/*", data_dir.display());
+    for path in glob(&glob_pattern)
+        .expect("Failed to read glob pattern")
+        .flatten()
+    {
+        if let Some((_, number)) = parse_sstable_name(&path) {
+            if !live.contains(&number) {
+                debug!("Removing obsolete file {}", path.display());
+                if let Err(e) = remove_file(&path) {
+                    error!("Failed deleting {} because {}", path.display(), e);
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::io::Read;
     use std::panic::{self, AssertUnwindSafe};
     use tempfile::TempDir;
 
@@ -298,43 +1024,73 @@ mod test {
             let temp_dir = TempDir::new().unwrap();
             let sstable_dir = temp_dir.path();
             let name = "test_merge_n_sstable_large".to_owned();
-            let mut sstable_o = create_sstable(0, name.clone(), sstable_dir);
-            let mut sstable_n = create_sstable(1, name.clone(), sstable_dir);
-            let mut sstable_m = create_sstable(2, name, sstable_dir);
-            let (mut dat, _) = sstable_m.open().unwrap();
+            let mut sstable_o = create_sstable(0, 1, name.clone(), sstable_dir);
+            let mut sstable_n = create_sstable(1, 2, name.clone(), sstable_dir);
+            let options = TableOptions {
+                block_size: 32,
+                ..Default::default()
+            };
             let mut map = BTreeMap::new();
-            map.insert(b"key1".to_vec(), b"value1".to_vec());
-            map.insert(b"key5".to_vec(), b"value2".to_vec());
-            map.insert(b"key3".to_vec(), b"value3".to_vec());
-            map.insert(b"key10".to_vec(), b"value6".to_vec());
-            sstable_o.write(&map).unwrap();
+            map.insert(b"key1".to_vec(), Value::Put(b"value1".to_vec()));
+            map.insert(b"key5".to_vec(), Value::Put(b"value2".to_vec()));
+            map.insert(b"key3".to_vec(), Value::Put(b"value3".to_vec()));
+            map.insert(b"key10".to_vec(), Value::Put(b"value6".to_vec()));
+            sstable_o.write(&map, &options).unwrap();
             map.clear();
 
-            map.insert(b"key2".to_vec(), b"value4".to_vec());
-            map.insert(b"key3".to_vec(), b"value5".to_vec());
-            map.insert(b"key4".to_vec(), b"value2".to_vec());
-            map.insert(b"key10".to_vec(), b"value9".to_vec());
-            map.insert(b"key11".to_vec(), b"value7".to_vec());
-            map.insert(b"key60".to_vec(), b"value7".to_vec());
-            sstable_n.write(&map).unwrap();
-
-            merge_two(&sstable_o, &sstable_n, &mut sstable_m, 0).unwrap();
-
-            let buf = &mut Vec::new();
-            dat.rewind().unwrap();
-            dat.read_to_end(buf).unwrap();
-            let string = String::from_utf8(buf.to_vec()).unwrap();
-            assert_eq!(
-                string,
-                "\u{4}\0key1\u{6}\0\0\0value1\
-                \u{5}\0key10\u{6}\0\0\0value9\
-                \u{5}\0key11\u{6}\0\0\0value7\
-                \u{4}\0key2\u{6}\0\0\0value4\
-                \u{4}\0key3\u{6}\0\0\0value5\
-                \u{4}\0key4\u{6}\0\0\0value2\
-                \u{4}\0key5\u{6}\0\0\0value2\
-                \u{5}\0key60\u{6}\0\0\0value7"
-            );
+            map.insert(b"key2".to_vec(), Value::Put(b"value4".to_vec()));
+            map.insert(b"key3".to_vec(), Value::Put(b"value5".to_vec()));
+            map.insert(b"key4".to_vec(), Value::Put(b"value2".to_vec()));
+            map.insert(b"key10".to_vec(), Value::Put(b"value9".to_vec()));
+            map.insert(b"key11".to_vec(), Value::Put(b"value7".to_vec()));
+            map.insert(b"key60".to_vec(), Value::Put(b"value7".to_vec()));
+            sstable_n.write(&map, &options).unwrap();
+
+            let mut blobs = BlobGc::new(sstable_dir.to_path_buf(), 0, Arc::new(AtomicU64::new(4)));
+            let compaction = Compaction {
+                level: 1,
+                output_level: 2,
+                inputs: vec![sstable_o, sstable_n],
+                target_file_size: u64::MAX,
+                drop_inputs: false,
+            };
+            let outputs = sstable_compaction(
+                &compaction,
+                &[],
+                None,
+                &name,
+                sstable_dir,
+                &AtomicU64::new(3),
+                &SyncPolicy::default(),
+                &options,
+                &mut blobs,
+                None,
+                now_millis(),
+            )
+            .unwrap();
+            assert_eq!(outputs.len(), 1);
+            assert_eq!(outputs[0].get_number(), 3);
+            assert_eq!(outputs[0].flush_number().unwrap(), 2);
+
+            let merged: Vec<(Vec<u8>, Value)> = outputs[0]
+                .iter()
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .collect();
+            let expected: Vec<(Vec<u8>, Value)> = [
+                (&b"key1"[..], &b"value1"[..]),
+                (&b"key10"[..], &b"value9"[..]),
+                (&b"key11"[..], &b"value7"[..]),
+                (&b"key2"[..], &b"value4"[..]),
+                (&b"key3"[..], &b"value5"[..]),
+                (&b"key4"[..], &b"value2"[..]),
+                (&b"key5"[..], &b"value2"[..]),
+                (&b"key60"[..], &b"value7"[..]),
+            ]
+            .iter()
+            .map(|(key, value)| (key.to_vec(), Value::Put(value.to_vec())))
+            .collect();
+            assert_eq!(merged, expected);
             drop(temp_dir);
         }));
         assert!(result.is_ok());
@@ -346,45 +1102,132 @@ mod test {
             let temp_dir = TempDir::new().unwrap();
             let sstable_dir = temp_dir.path();
             let name = "test_merge_o_sstable_large".to_owned();
-            let mut sstable_o = create_sstable(0, name.clone(), sstable_dir);
-            let mut sstable_n = create_sstable(1, name.clone(), sstable_dir);
-            let mut sstable_m = create_sstable(2, name, sstable_dir);
-            let (mut dat, _) = sstable_m.open().unwrap();
+            let mut sstable_o = create_sstable(0, 1, name.clone(), sstable_dir);
+            let mut sstable_n = create_sstable(1, 2, name.clone(), sstable_dir);
+            let options = TableOptions {
+                block_size: 32,
+                ..Default::default()
+            };
             let mut map = BTreeMap::new();
-            map.insert(b"key2".to_vec(), b"value4".to_vec());
-            map.insert(b"key3".to_vec(), b"value5".to_vec());
-            map.insert(b"key4".to_vec(), b"value2".to_vec());
-            map.insert(b"key10".to_vec(), b"value9".to_vec());
-            map.insert(b"key11".to_vec(), b"value7".to_vec());
-            map.insert(b"key60".to_vec(), b"value7".to_vec());
-            sstable_o.write(&map).unwrap();
+            map.insert(b"key2".to_vec(), Value::Put(b"value4".to_vec()));
+            map.insert(b"key3".to_vec(), Value::Put(b"value5".to_vec()));
+            map.insert(b"key4".to_vec(), Value::Put(b"value2".to_vec()));
+            map.insert(b"key10".to_vec(), Value::Put(b"value9".to_vec()));
+            map.insert(b"key11".to_vec(), Value::Put(b"value7".to_vec()));
+            map.insert(b"key60".to_vec(), Value::Put(b"value7".to_vec()));
+            sstable_o.write(&map, &options).unwrap();
             map.clear();
 
-            map.insert(b"key1".to_vec(), b"value1".to_vec());
-            map.insert(b"key5".to_vec(), b"value2".to_vec());
-            map.insert(b"key3".to_vec(), b"value3".to_vec());
-            map.insert(b"key10".to_vec(), b"value6".to_vec());
-            sstable_n.write(&map).unwrap();
-
-            merge_two(&sstable_o, &sstable_n, &mut sstable_m, 0).unwrap();
-
-            let buf = &mut Vec::new();
-            dat.rewind().unwrap();
-            dat.read_to_end(buf).unwrap();
-            let string = String::from_utf8(buf.to_vec()).unwrap();
-            assert_eq!(
-                string,
-                "\u{4}\0key1\u{6}\0\0\0value1\
-                \u{5}\0key10\u{6}\0\0\0value6\
-                \u{5}\0key11\u{6}\0\0\0value7\
-                \u{4}\0key2\u{6}\0\0\0value4\
-                \u{4}\0key3\u{6}\0\0\0value3\
-                \u{4}\0key4\u{6}\0\0\0value2\
-                \u{4}\0key5\u{6}\0\0\0value2\
-                \u{5}\0key60\u{6}\0\0\0value7"
-            );
+            map.insert(b"key1".to_vec(), Value::Put(b"value1".to_vec()));
+            map.insert(b"key5".to_vec(), Value::Put(b"value2".to_vec()));
+            map.insert(b"key3".to_vec(), Value::Put(b"value3".to_vec()));
+            map.insert(b"key10".to_vec(), Value::Put(b"value6".to_vec()));
+            sstable_n.write(&map, &options).unwrap();
+
+            let mut blobs = BlobGc::new(sstable_dir.to_path_buf(), 0, Arc::new(AtomicU64::new(4)));
+            let compaction = Compaction {
+                level: 1,
+                output_level: 2,
+                inputs: vec![sstable_o, sstable_n],
+                target_file_size: u64::MAX,
+                drop_inputs: false,
+            };
+            let outputs = sstable_compaction(
+                &compaction,
+                &[],
+                None,
+                &name,
+                sstable_dir,
+                &AtomicU64::new(3),
+                &SyncPolicy::default(),
+                &options,
+                &mut blobs,
+                None,
+                now_millis(),
+            )
+            .unwrap();
+            assert_eq!(outputs.len(), 1);
+
+            let merged: Vec<(Vec<u8>, Value)> = outputs[0]
+                .iter()
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .collect();
+            let expected: Vec<(Vec<u8>, Value)> = [
+                (&b"key1"[..], &b"value1"[..]),
+                (&b"key10"[..], &b"value6"[..]),
+                (&b"key11"[..], &b"value7"[..]),
+                (&b"key2"[..], &b"value4"[..]),
+                (&b"key3"[..], &b"value3"[..]),
+                (&b"key4"[..], &b"value2"[..]),
+                (&b"key5"[..], &b"value2"[..]),
+                (&b"key60"[..], &b"value7"[..]),
+            ]
+            .iter()
+            .map(|(key, value)| (key.to_vec(), Value::Put(value.to_vec())))
+            .collect();
+            assert_eq!(merged, expected);
             drop(temp_dir);
         }));
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_compaction_drops_tombstones() {
+        let temp_dir = TempDir::new().unwrap();
+        let sstable_dir = temp_dir.path();
+        let name = "test_tombstones".to_owned();
+        let options = TableOptions::default();
+        let mut older = create_sstable(1, 1, name.clone(), sstable_dir);
+        let mut newer = create_sstable(1, 2, name.clone(), sstable_dir);
+        let mut map = BTreeMap::new();
+        for key in ["a", "b", "e", "x"] {
+            map.insert(key.as_bytes().to_vec(), Value::Put(b"old".to_vec()));
+        }
+        older.write(&map, &options).unwrap();
+        map.clear();
+        for key in ["a", "e", "x"] {
+            map.insert(key.as_bytes().to_vec(), Value::Delete);
+        }
+        map.insert(b"y".to_vec(), Value::Delete);
+        newer.write(&map, &options).unwrap();
+
+        let compaction = Compaction {
+            level: 1,
+            output_level: 2,
+            inputs: vec![older, newer],
+            target_file_size: u64::MAX,
+            drop_inputs: false,
+        };
+        // A table outside the compaction may still hold `d` to `f`, and `y`.
+        let ranges = [
+            (b"d".to_vec(), b"f".to_vec()),
+            (b"y".to_vec(), b"z".to_vec()),
+        ];
+        let mut blobs = BlobGc::new(sstable_dir.to_path_buf(), 0, Arc::new(AtomicU64::new(9)));
+        let outputs = sstable_compaction(
+            &compaction,
+            &ranges,
+            None,
+            &name,
+            sstable_dir,
+            &AtomicU64::new(3),
+            &SyncPolicy::default(),
+            &options,
+            &mut blobs,
+            None,
+            now_millis(),
+        )
+        .unwrap();
+        let merged: Vec<(Vec<u8>, Value)> =
+            outputs[0].iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(
+            merged,
+            vec![
+                (b"b".to_vec(), Value::Put(b"old".to_vec())),
+                (b"e".to_vec(), Value::Delete),
+                (b"y".to_vec(), Value::Delete),
+            ]
+        );
+    }
 }
@@ -1,9 +1,18 @@
 #[cfg(test)]
 mod test {
-    use crate::sstable::sst::SSTable;
+    use crate::sstable::blob::{blob_path, BlobReader, BlobRef, BlobWriter};
+    use crate::sstable::compression::Compression;
+    use crate::sstable::discover::discover_sstables;
+    use crate::sstable::filter::{bloom_hash, BloomFilter, DelimitedPrefix, FixedPrefix};
+    use crate::sstable::sst::{create_sstable, sstable_data_dir, SSTable, TableOptions};
+    use crate::sstable::value::Value;
+    use crate::utils::fs::SyncPolicy;
     use std::{
         collections::BTreeMap,
+        fs::{File, OpenOptions},
+        io::{ErrorKind, Seek, SeekFrom, Write},
         panic::{self, AssertUnwindSafe},
+        sync::Arc,
     };
     use tempfile::TempDir;
 
@@ -15,20 +24,17 @@ mod test {
                 Err(_) => panic!("Failed creating tempdir."),
             };
             let filename = temp_dir.path().join("test.sstable");
-            let mut sstable = match SSTable::new(filename, 1, true, true, true) {
-                Ok(sst) => sst,
-                Err(_) => panic!("Failed creating sstable."),
-            };
+            let mut sstable = SSTable::new(filename, 1);
             let key = b"test_key";
             let value = b"test_value";
             let mut store = BTreeMap::new();
-            store.insert(key.to_vec(), value.to_vec());
-            match sstable.write(&store) {
+            store.insert(key.to_vec(), Value::Put(value.to_vec()));
+            match sstable.write(&store, &TableOptions::default()) {
                 Ok(_) => (),
                 Err(_) => panic!("Failed write to sstable."),
             };
             let value_read = match sstable.search(key) {
-                Ok(Some(v)) => v,
+                Ok(Some(Value::Put(v))) => v,
                 Err(e) => panic!("{}", e),
                 _ => panic!("Failed to read value."),
             };
@@ -37,4 +43,328 @@ mod test {
         }));
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_search_across_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut sstable = SSTable::new(temp_dir.path().join("test.sstable"), 1);
+        let mut map = BTreeMap::new();
+        for i in 0..500 {
+            map.insert(
+                format!("key{:04}", i).into_bytes(),
+                Value::Put(format!("value{}", i).into_bytes()),
+            );
+        }
+        let options = TableOptions {
+            block_size: 64,
+            ..Default::default()
+        };
+        sstable.write(&map, &options).unwrap();
+
+        for (key, value) in map.iter() {
+            assert_eq!(sstable.search(key).unwrap().as_ref(), Some(value));
+        }
+        assert_eq!(sstable.search(b"key").unwrap(), None);
+        assert_eq!(sstable.search(b"key0100a").unwrap(), None);
+        assert_eq!(sstable.search(b"key9999").unwrap(), None);
+
+        let entries: Vec<(Vec<u8>, Value)> = sstable
+            .iter()
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        assert_eq!(entries, map.clone().into_iter().collect::<Vec<_>>());
+
+        for start in [&b"key0250"[..], b"key0250a", b"key", b"key9"] {
+            let keys: Vec<Vec<u8>> = sstable
+                .iter_from(start)
+                .unwrap()
+                .map(|entry| entry.unwrap().0)
+                .collect();
+            let expected: Vec<Vec<u8>> = map
+                .keys()
+                .filter(|key| key.as_slice() >= start)
+                .cloned()
+                .collect();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn test_compressed_sstables() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut map = BTreeMap::new();
+        for i in 0..500 {
+            map.insert(
+                format!("key{:04}", i).into_bytes(),
+                Value::Put(vec![b'v'; 100]),
+            );
+        }
+        let raw_size = {
+            let mut sstable = SSTable::new(temp_dir.path().join("raw.sstable"), 1);
+            sstable.write(&map, &TableOptions::default()).unwrap();
+            temp_dir
+                .path()
+                .join("raw.sstable")
+                .metadata()
+                .unwrap()
+                .len()
+        };
+
+        for compression in [Compression::Lz4, Compression::Zstd, Compression::Snappy] {
+            let path = temp_dir.path().join(format!("{:?}.sstable", compression));
+            let mut sstable = SSTable::new(path.clone(), 1);
+            let options = TableOptions {
+                compression,
+                ..Default::default()
+            };
+            sstable.write(&map, &options).unwrap();
+            assert!(path.metadata().unwrap().len() < raw_size / 2);
+
+            for (key, value) in map.iter() {
+                assert_eq!(sstable.search(key).unwrap().as_ref(), Some(value));
+            }
+            let entries: Vec<(Vec<u8>, Value)> = sstable
+                .iter()
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .collect();
+            assert_eq!(entries, map.clone().into_iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_value_types() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut sstable = SSTable::new(temp_dir.path().join("test.sstable"), 1);
+        let mut map = BTreeMap::new();
+        map.insert(b"deleted".to_vec(), Value::Delete);
+        map.insert(b"empty".to_vec(), Value::Put(vec![]));
+        sstable.write(&map, &TableOptions::default()).unwrap();
+
+        assert_eq!(sstable.search(b"deleted").unwrap(), Some(Value::Delete));
+        assert_eq!(sstable.search(b"empty").unwrap(), Some(Value::Put(vec![])));
+        assert_eq!(sstable.search(b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_keys_must_be_increasing() {
+        let temp_dir = TempDir::new().unwrap();
+        let sstable = SSTable::new(temp_dir.path().join("test.sstable"), 1);
+        let mut builder = sstable.builder(&TableOptions::default()).unwrap();
+        let value = Value::Put(b"value".to_vec());
+        builder.add(b"key2", &value).unwrap();
+        let err = builder.add(b"key1", &value).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = builder.add(b"key2", &Value::Delete).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_discover_sstables() {
+        let temp_dir = TempDir::new().unwrap();
+        let name = "test_discover_sstables".to_owned();
+        let mut map = BTreeMap::new();
+        map.insert(b"key".to_vec(), Value::Put(b"value".to_vec()));
+        for (level, number) in [(3, 12), (1, 2), (2, 7)] {
+            let mut sstable = create_sstable(level, number, name.clone(), temp_dir.path());
+            sstable.write(&map, &TableOptions::default()).unwrap();
+        }
+        let data_dir = sstable_data_dir(temp_dir.path(), &name);
+        File::create(data_dir.join("1-000009.index")).unwrap();
+        File::create(data_dir.join("notes.txt")).unwrap();
+
+        let discovered = discover_sstables(&data_dir);
+        let found: Vec<(u64, u16)> = discovered
+            .iter()
+            .map(|(number, sstable)| (*number, sstable.get_level()))
+            .collect();
+        assert_eq!(found, vec![(2, 1), (7, 2), (12, 3)]);
+    }
+
+    fn corrupt(path: &std::path::Path, offset: u64) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(b"X").unwrap();
+    }
+
+    fn written_sstable(temp_dir: &TempDir) -> SSTable {
+        write_sstable(temp_dir, &TableOptions::default())
+    }
+
+    fn write_sstable(temp_dir: &TempDir, options: &TableOptions) -> SSTable {
+        let filename = temp_dir.path().join("test.sstable");
+        let mut sstable = SSTable::new(filename, 1);
+        let mut map = BTreeMap::new();
+        map.insert(b"key1".to_vec(), Value::Put(b"value1".to_vec()));
+        map.insert(b"key2".to_vec(), Value::Put(b"value2".to_vec()));
+        sstable.write(&map, options).unwrap();
+        sstable
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let keys: Vec<Vec<u8>> = (0..10_000)
+            .map(|i| format!("key{}", i).into_bytes())
+            .collect();
+        let hashes: Vec<u32> = keys.iter().map(|key| bloom_hash(key)).collect();
+        let filter = BloomFilter::decode(&BloomFilter::build(&hashes, 10).encode()).unwrap();
+        assert!(keys.iter().all(|key| filter.may_contain(key)));
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.may_contain(format!("missing{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_prefix_filter() {
+        let temp_dir = TempDir::new().unwrap();
+        let options = TableOptions {
+            prefix_extractor: Some(Arc::new(DelimitedPrefix(b'/'))),
+            ..Default::default()
+        };
+        let mut sstable = SSTable::new(temp_dir.path().join("test.sstable"), 1);
+        let mut map = BTreeMap::new();
+        for key in [&b"tenant1/user/1"[..], b"tenant1/user/2", b"tenant3/user/1"] {
+            map.insert(key.to_vec(), Value::Put(b"value".to_vec()));
+        }
+        sstable.write(&map, &options).unwrap();
+
+        let extractor = DelimitedPrefix(b'/');
+        assert!(sstable.may_contain_prefix(b"tenant1/", &extractor).unwrap());
+        assert!(sstable.may_contain_prefix(b"tenant3/", &extractor).unwrap());
+        assert!(!sstable.may_contain_prefix(b"tenant2/", &extractor).unwrap());
+        // Prefixes from another extractor are not in the filter.
+        assert!(sstable
+            .may_contain_prefix(b"tenant2", &FixedPrefix(7))
+            .unwrap());
+        assert_eq!(
+            sstable.search(b"tenant1/user/2").unwrap(),
+            Some(Value::Put(b"value".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_filter_skips_block_reads() {
+        let temp_dir = TempDir::new().unwrap();
+        let sstable = written_sstable(&temp_dir);
+        corrupt(&temp_dir.path().join("test.sstable"), 12);
+
+        // The filter rules the key out, the corrupted block is never read.
+        assert_eq!(sstable.search(b"key15").unwrap(), None);
+        assert!(sstable.search(b"key1").is_err());
+    }
+
+    #[test]
+    fn test_sstable_without_filter() {
+        let temp_dir = TempDir::new().unwrap();
+        let options = TableOptions {
+            bits_per_key: 0,
+            ..Default::default()
+        };
+        let sstable = write_sstable(&temp_dir, &options);
+        assert_eq!(
+            sstable.search(b"key2").unwrap(),
+            Some(Value::Put(b"value2".to_vec()))
+        );
+        corrupt(&temp_dir.path().join("test.sstable"), 12);
+
+        assert!(sstable.search(b"key15").is_err());
+    }
+
+    #[test]
+    fn test_corrupted_block_is_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let sstable = written_sstable(&temp_dir);
+        // Inside the value of the first pair.
+        corrupt(&temp_dir.path().join("test.sstable"), 12);
+
+        let err = sstable.search(b"key1").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        let err = sstable.iter().unwrap().next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_corrupted_footer_is_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let sstable = written_sstable(&temp_dir);
+        let path = temp_dir.path().join("test.sstable");
+        let len = path.metadata().unwrap().len();
+        // Inside the index handle.
+        corrupt(&path, len - 40);
+
+        let err = sstable.search(b"key2").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("test.sstable"), "{}", err);
+    }
+
+    #[test]
+    fn test_truncated_sstable_is_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let sstable = written_sstable(&temp_dir);
+        let file = OpenOptions::new()
+            .write(true)
+            .open(temp_dir.path().join("test.sstable"))
+            .unwrap();
+        file.set_len(20).unwrap();
+
+        let err = sstable.search(b"key2").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_blob_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = BlobWriter::create(temp_dir.path(), 7).unwrap();
+        let first = writer.add(b"key1", &[1; 1000]).unwrap();
+        let second = writer.add(b"key2", b"").unwrap();
+        assert_eq!(writer.finish(&SyncPolicy::default()).unwrap(), 7);
+        assert_eq!(first.file, 7);
+        assert_eq!(second.offset, first.size);
+
+        let reader = BlobReader::new(temp_dir.path().to_path_buf());
+        assert_eq!(reader.read(b"key1", &first).unwrap(), vec![1; 1000]);
+        assert_eq!(reader.read(b"key2", &second).unwrap(), b"");
+        let err = reader.read(b"key2", &first).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(blob_path(temp_dir.path(), 7))
+            .unwrap();
+        file.seek(SeekFrom::Start(100)).unwrap();
+        file.write_all(b"garbage").unwrap();
+        let err = reader.read(b"key1", &first).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(reader.read(b"key2", &second).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_sstable_lists_blob_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut sstable = SSTable::new(temp_dir.path().join("test.sstable"), 1);
+        let blob = BlobRef {
+            file: 3,
+            offset: 1 << 40,
+            size: 200,
+        };
+        let mut map = BTreeMap::new();
+        map.insert(b"key1".to_vec(), Value::Blob(blob));
+        map.insert(b"key2".to_vec(), Value::Put(b"value".to_vec()));
+        map.insert(
+            b"key3".to_vec(),
+            Value::Blob(BlobRef {
+                file: 2,
+                offset: 0,
+                size: 10,
+            }),
+        );
+        sstable.write(&map, &TableOptions::default()).unwrap();
+        let sstable = SSTable::new(temp_dir.path().join("test.sstable"), 1);
+        assert_eq!(sstable.search(b"key1").unwrap(), Some(Value::Blob(blob)));
+        assert_eq!(sstable.blob_files().unwrap(), vec![2, 3]);
+    }
 }
@@ -0,0 +1,108 @@
+use crate::sstable::blob::BlobRef;
+use crate::utils::futil;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Kind of a record, written as a single byte in front of its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Put = 1,
+    Delete = 2,
+    Blob = 3,
+    Expiring = 4,
+}
+
+impl ValueType {
+    pub fn from_tag(tag: u8) -> Result<ValueType> {
+        match tag {
+            1 => Ok(ValueType::Put),
+            2 => Ok(ValueType::Delete),
+            3 => Ok(ValueType::Blob),
+            4 => Ok(ValueType::Expiring),
+            _ => Err(futil::corruption(format!("unknown value type {}", tag))),
+        }
+    }
+}
+
+/// What the store holds for a key: a value, or a marker that it was deleted.
+///
+/// The marker has to be kept until every older value of the key is
+/// compacted away, otherwise the older value would show up again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Put(Vec<u8>),
+    Delete,
+    /// A value kept in a blob file, see `BlobOptions`. Only found in sstables.
+    Blob(BlobRef),
+    /// A value that reads as deleted from `expires_at` on, see `KVStore::set_with_ttl`.
+    Expiring {
+        value: Vec<u8>,
+        expires_at: u64,
+    },
+}
+
+/// Milliseconds since the Unix epoch, the clock expiry times are on.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Put(_) => ValueType::Put,
+            Value::Delete => ValueType::Delete,
+            Value::Blob(_) => ValueType::Blob,
+            Value::Expiring { .. } => ValueType::Expiring,
+        }
+    }
+
+    /// `true` for a deleted key, or a value that expired by `now`.
+    pub fn is_deleted(&self, now: u64) -> bool {
+        match self {
+            Value::Delete => true,
+            Value::Expiring { expires_at, .. } => *expires_at <= now,
+            Value::Put(_) | Value::Blob(_) => false,
+        }
+    }
+
+    /**
+     * Values are written after their key as:
+     *
+     * |<- Type (1) ->|<- Val length (varint) ->|<- Value ->|
+     *
+     * A `Delete` has no length nor value, a `Blob` is followed by its
+     * `BlobRef` instead. An `Expiring` value has its expiry time in front:
+     *
+     * |<- Type (1) ->|<- Expires at (varint) ->|<- Val length (varint) ->|<- Value ->|
+     */
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        buf.write_u8(self.value_type() as u8)?;
+        match self {
+            Value::Put(value) => futil::set_value(buf, value.len(), value),
+            Value::Delete => Ok(()),
+            Value::Blob(blob) => blob.encode(buf),
+            Value::Expiring { value, expires_at } => {
+                futil::write_varint(buf, *expires_at)?;
+                futil::set_value(buf, value.len(), value)
+            }
+        }
+    }
+
+    pub fn decode<R: Read>(reader: &mut R) -> Result<Value> {
+        match ValueType::from_tag(reader.read_u8()?)? {
+            ValueType::Put => Ok(Value::Put(futil::get_value(reader)?)),
+            ValueType::Delete => Ok(Value::Delete),
+            ValueType::Blob => Ok(Value::Blob(BlobRef::decode(reader)?)),
+            ValueType::Expiring => {
+                let expires_at = futil::read_varint(reader)?;
+                Ok(Value::Expiring {
+                    value: futil::get_value(reader)?,
+                    expires_at,
+                })
+            }
+        }
+    }
+}
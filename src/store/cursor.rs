@@ -0,0 +1,297 @@
+use std::io::Result;
+
+use crate::sstable::blob::BlobReader;
+use crate::sstable::value::Value;
+
+/**
+ * A position in a sorted run of key-value pairs that can move both ways.
+ *
+ * A cursor starts out unpositioned, one of the `seek` methods places it.
+ * Moving past either end leaves it invalid until the next seek.
+ */
+pub(crate) trait Cursor: Send {
+    fn valid(&self) -> bool;
+    /// Only called on a valid cursor.
+    fn key(&self) -> &[u8];
+    /// Only called on a valid cursor.
+    fn value(&self) -> &Value;
+    /// Move to the first key not less than `key`.
+    fn seek(&mut self, key: &[u8]) -> Result<()>;
+    /// Move to the last key not greater than `key`.
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()>;
+    fn seek_to_first(&mut self) -> Result<()>;
+    fn seek_to_last(&mut self) -> Result<()>;
+    fn next(&mut self) -> Result<()>;
+    fn prev(&mut self) -> Result<()>;
+}
+
+/// Cursor over a snapshot of the memtable.
+pub(crate) struct MemCursor {
+    entries: Vec<(Vec<u8>, Value)>,
+    position: Option<usize>,
+}
+
+impl MemCursor {
+    pub(crate) fn new(entries: Vec<(Vec<u8>, Value)>) -> MemCursor {
+        MemCursor {
+            entries,
+            position: None,
+        }
+    }
+
+    fn place(&mut self, position: usize) {
+        self.position = if position < self.entries.len() {
+            Some(position)
+        } else {
+            None
+        };
+    }
+}
+
+impl Cursor for MemCursor {
+    fn valid(&self) -> bool {
+        self.position.is_some()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.entries[self.position.unwrap()].0
+    }
+
+    fn value(&self) -> &Value {
+        &self.entries[self.position.unwrap()].1
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let position = self.entries.partition_point(|(k, _)| k.as_slice() < key);
+        self.place(position);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let position = self.entries.partition_point(|(k, _)| k.as_slice() <= key);
+        self.position = position.checked_sub(1);
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.place(0);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.position = self.entries.len().checked_sub(1);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if let Some(position) = self.position {
+            self.place(position + 1);
+        }
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.position = self.position.and_then(|position| position.checked_sub(1));
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/**
+ * A cursor over the live keys of a `KVStore`, moving in either direction.
+ *
+ * It merges a snapshot of the memtable with the sstables of the store at
+ * the time it was created. Each sstable is read one block at a time, so a
+ * query like "the last 10 keys" only reads the blocks it needs:
+ *
+ * ```
+ * use rkv::store::lsm_store::KVStore;
+ *
+ * let dir = tempfile::tempdir().unwrap();
+ * let mut store = KVStore::new("events".to_owned(), 100, dir.path().to_path_buf());
+ * for i in 0..20 {
+ *     store.set(format!("event:{:03}", i).as_bytes(), b"...").unwrap();
+ * }
+ * let mut cursor = store.cursor().unwrap();
+ * cursor.seek_to_last().unwrap();
+ * let mut latest = vec![];
+ * while cursor.valid() && latest.len() < 3 {
+ *     latest.push(cursor.key().unwrap().to_vec());
+ *     cursor.prev().unwrap();
+ * }
+ * assert_eq!(latest, vec![b"event:019".to_vec(), b"event:018".to_vec(), b"event:017".to_vec()]);
+ * ```
+ *
+ * Deleted keys are skipped, and so are keys that had expired when the
+ * cursor was created. When several sources hold a key, the most recent
+ * one decides its value.
+ */
+pub struct StoreCursor {
+    /// Oldest first, the memtable is last.
+    children: Vec<Box<dyn Cursor>>,
+    /// The child the cursor is at, the newest one holding the current key.
+    current: Option<usize>,
+    direction: Direction,
+    blobs: BlobReader,
+    /// Expiring values are checked against this time.
+    now: u64,
+    /// The value of the current key, when it is kept in a blob file.
+    blob_value: Option<Vec<u8>>,
+}
+
+impl StoreCursor {
+    pub(crate) fn new(children: Vec<Box<dyn Cursor>>, blobs: BlobReader, now: u64) -> StoreCursor {
+        StoreCursor {
+            children,
+            current: None,
+            direction: Direction::Forward,
+            blobs,
+            now,
+            blob_value: None,
+        }
+    }
+
+    /// `true` while the cursor is at a key.
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// The key the cursor is at, `None` if it is not valid.
+    pub fn key(&self) -> Option<&[u8]> {
+        self.current.map(|i| self.children[i].key())
+    }
+
+    /// The value of the key the cursor is at, `None` if it is not valid.
+    pub fn value(&self) -> Option<&[u8]> {
+        match self.children[self.current?].value() {
+            Value::Put(value) | Value::Expiring { value, .. } => Some(value),
+            Value::Blob(_) => self.blob_value.as_deref(),
+            Value::Delete => None,
+        }
+    }
+
+    /// Move to the first key not less than `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        for child in self.children.iter_mut() {
+            child.seek(key)?;
+        }
+        self.direction = Direction::Forward;
+        self.settle()
+    }
+
+    /// Move to the last key not greater than `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        for child in self.children.iter_mut() {
+            child.seek_for_prev(key)?;
+        }
+        self.direction = Direction::Reverse;
+        self.settle()
+    }
+
+    pub fn seek_to_first(&mut self) -> Result<()> {
+        for child in self.children.iter_mut() {
+            child.seek_to_first()?;
+        }
+        self.direction = Direction::Forward;
+        self.settle()
+    }
+
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        for child in self.children.iter_mut() {
+            child.seek_to_last()?;
+        }
+        self.direction = Direction::Reverse;
+        self.settle()
+    }
+
+    /// Move to the next key. Does nothing on a cursor that is not valid.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<()> {
+        if let Some(key) = self.key().map(|key| key.to_vec()) {
+            self.step(&key, Direction::Forward)?;
+            self.settle()?;
+        }
+        Ok(())
+    }
+
+    /// Move to the previous key. Does nothing on a cursor that is not valid.
+    pub fn prev(&mut self) -> Result<()> {
+        if let Some(key) = self.key().map(|key| key.to_vec()) {
+            self.step(&key, Direction::Reverse)?;
+            self.settle()?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Move every child past `key` in `direction`.
+     *
+     * Moving forward, every child is kept at its first key after the
+     * current one, moving in reverse at its last key before it. Children
+     * are repositioned when the direction changes.
+     */
+    fn step(&mut self, key: &[u8], direction: Direction) -> Result<()> {
+        let turned = self.direction != direction;
+        for child in self.children.iter_mut() {
+            if turned {
+                match direction {
+                    Direction::Forward => child.seek(key)?,
+                    Direction::Reverse => child.seek_for_prev(key)?,
+                }
+            }
+            if child.valid() && child.key() == key {
+                match direction {
+                    Direction::Forward => child.next()?,
+                    Direction::Reverse => child.prev()?,
+                }
+            }
+        }
+        self.direction = direction;
+        Ok(())
+    }
+
+    /// Find the key the cursor is at, skipping keys whose newest value is a tombstone or expired.
+    fn settle(&mut self) -> Result<()> {
+        self.blob_value = None;
+        loop {
+            self.current = None;
+            for (i, child) in self.children.iter().enumerate() {
+                if !child.valid() {
+                    continue;
+                }
+                let closer = match self.current {
+                    None => true,
+                    Some(current) => {
+                        let current_key = self.children[current].key();
+                        match self.direction {
+                            Direction::Forward => child.key() <= current_key,
+                            Direction::Reverse => child.key() >= current_key,
+                        }
+                    }
+                };
+                if closer {
+                    self.current = Some(i);
+                }
+            }
+            match self.current {
+                Some(current) if self.children[current].value().is_deleted(self.now) => {
+                    let key = self.children[current].key().to_vec();
+                    self.step(&key, self.direction)?;
+                }
+                Some(current) => {
+                    let child = &self.children[current];
+                    if let Value::Blob(blob) = child.value() {
+                        self.blob_value = Some(self.blobs.read(child.key(), blob)?);
+                    }
+                    return Ok(());
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
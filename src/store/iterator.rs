@@ -0,0 +1,156 @@
+use std::io::Result;
+use std::iter::Peekable;
+use std::ops::{Bound, RangeBounds};
+
+use crate::sstable::blob::BlobReader;
+use crate::sstable::value::Value;
+
+/// Key-value pairs in key order, or the error that ended them.
+pub type KVIterator = Box<dyn Iterator<Item = Result<(Vec<u8>, Value)>> + Send>;
+
+/**
+ * Merge sorted sources into a single sorted stream.
+ *
+ * Sources are ordered oldest first. When several sources hold the same
+ * key, the pair from the most recent source is returned and the others are
+ * skipped. A source that fails ends the stream with its error.
+ */
+pub struct MergingIterator {
+    sources: Vec<Peekable<KVIterator>>,
+    failed: bool,
+}
+
+impl MergingIterator {
+    pub fn new(sources: Vec<KVIterator>) -> MergingIterator {
+        MergingIterator {
+            sources: sources
+                .into_iter()
+                .map(|source| source.peekable())
+                .collect(),
+            failed: false,
+        }
+    }
+
+    /// Index of the source holding the smallest key, the newest one on ties.
+    fn smallest(&mut self) -> Result<Option<usize>> {
+        for source in self.sources.iter_mut() {
+            if let Some(Err(_)) = source.peek() {
+                if let Some(Err(e)) = source.next() {
+                    return Err(e);
+                }
+            }
+        }
+        let mut smallest: Option<(usize, &Vec<u8>)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            if let Some(Ok((key, _))) = source.peek() {
+                match smallest {
+                    Some((_, smallest_key)) if smallest_key < key => (),
+                    _ => smallest = Some((i, key)),
+                }
+            }
+        }
+        Ok(smallest.map(|(i, _)| i))
+    }
+}
+
+impl Iterator for MergingIterator {
+    type Item = Result<(Vec<u8>, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let newest = match self.smallest() {
+            Ok(newest) => newest?,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+        let entry = match self.sources[newest].next() {
+            Some(Ok(entry)) => entry,
+            outcome => return outcome,
+        };
+        for source in self.sources.iter_mut() {
+            while let Some(Ok((key, _))) = source.peek() {
+                if key != &entry.0 {
+                    break;
+                }
+                source.next();
+            }
+        }
+        Some(Ok(entry))
+    }
+}
+
+/// Live key-value pairs of a `KVStore` within a range, in key order.
+///
+/// Deleted and expired keys are skipped. The iterator reads a snapshot of
+/// the store taken when it was created, later writes are not visible and
+/// expiry is checked against the time of the snapshot. Values kept in blob
+/// files are read as their keys come up.
+pub struct StoreIterator {
+    merged: MergingIterator,
+    blobs: BlobReader,
+    now: u64,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    done: bool,
+}
+
+impl StoreIterator {
+    pub(crate) fn new<R: RangeBounds<Vec<u8>>>(
+        merged: MergingIterator,
+        range: &R,
+        blobs: BlobReader,
+        now: u64,
+    ) -> Self {
+        StoreIterator {
+            merged,
+            blobs,
+            now,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            done: false,
+        }
+    }
+}
+
+impl Iterator for StoreIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let (key, value) = match self.merged.next()? {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            if let Bound::Excluded(start) = &self.start {
+                if &key == start {
+                    continue;
+                }
+            }
+            let in_range = match &self.end {
+                Bound::Included(end) => &key <= end,
+                Bound::Excluded(end) => &key < end,
+                Bound::Unbounded => true,
+            };
+            if !in_range {
+                self.done = true;
+                break;
+            }
+            match self.blobs.resolve(&key, value, self.now) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
@@ -1,24 +1,51 @@
-use log::{debug, error};
-use std::io::Result;
+use log::{debug, warn};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs::read_dir;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::{collections::BTreeMap, path::PathBuf};
+use std::time::{Duration, Instant};
 
-use glob::glob;
-
-use crate::sstable::constants::{RKV, TOMBSTONE};
-use crate::sstable::sst::{create_sstable, sstable_compaction, SSTable};
+use crate::compaction::filter::CompactionFilter;
+use crate::compaction::strategy::{Compaction, CompactionStrategy};
+use crate::manifest::version_edit::{TableMeta, VersionEdit};
+use crate::manifest::version_set::Manifest;
+use crate::sstable::blob::{
+    blob_path, parse_blob_name, remove_obsolete_blob_files, BlobGc, BlobOptions, BlobReader,
+    BlobWriter,
+};
+use crate::sstable::constants::RKV;
+use crate::sstable::sst::{
+    create_sstable, parse_sstable_name, remove_obsolete_sstables, sstable_compaction,
+    sstable_data_dir, sstable_path, ObsoleteFiles, SSTable, TableOptions,
+};
+use crate::sstable::value::Value;
+use crate::store::cursor::{Cursor, MemCursor, StoreCursor};
+use crate::store::iterator::{KVIterator, MergingIterator, StoreIterator};
+use crate::store::options::Options;
+use crate::store::stall::{Stall, WriteStallOptions, WriteStallStats};
+use crate::utils::clock::Clock;
+use crate::utils::fs::SyncPolicy;
+use crate::utils::rate_limiter::{Priority, RateLimiter};
+use crate::utils::worker::Worker;
+use crate::wal::journal::{LogRecord, WriteAheadLog};
 
 /// A key value store implemented as an LSM Tree.
 ///
 /// # Example
 /// ```
-/// use std::path::PathBuf;
 /// use rkv::store::lsm_store::KVStore;
 ///
-/// let mut store = KVStore::new("database".to_owned(), 100, PathBuf::from("/tmp/.tmp20aefd00/book_ratings/"));
-/// store.set(b"The Rust Programming language", b"5");
-/// if let Some(v) = store.get(b"The Rust Programming language") {
+/// let dir = tempfile::tempdir().unwrap();
+/// let book_ratings = dir.path().join("book_ratings");
+/// let mut store = KVStore::new("database".to_owned(), 100, book_ratings);
+/// store.set(b"The Rust Programming language", b"5").unwrap();
+/// if let Ok(Some(v)) = store.get(b"The Rust Programming language") {
 ///     assert_eq!(v.as_slice(), b"5");
 /// }
 /// ```
@@ -26,28 +53,149 @@ use crate::sstable::sst::{create_sstable, sstable_compaction, SSTable};
 pub struct KVStore {
     name: String,
     /// memtable is
-    memtable: Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    memtable: Arc<Mutex<BTreeMap<Vec<u8>, Value>>>,
     mem_size: Arc<Mutex<usize>>,
     max_bytes: usize,
+    /// Full memtables waiting for the flush thread, oldest first.
+    immutables: Arc<Mutex<Vec<Arc<ImmutableMemtable>>>>,
+    max_immutables: usize,
     sstables: Arc<Mutex<Vec<SSTable>>>,
     sstable_dir: PathBuf,
+    /// Number given to the next sstable, larger numbers hold more recent writes.
+    next_file_number: Arc<AtomicU64>,
+    /// Every mutation is appended here before it reaches the memtable.
+    wal: Arc<Mutex<WriteAheadLog>>,
+    /// The committed set of sstables.
+    manifest: Arc<Mutex<Manifest>>,
+    sync: SyncPolicy,
+    table_options: TableOptions,
+    blob_options: BlobOptions,
+    /// Decides which tables to compact.
+    picker: Arc<dyn CompactionStrategy>,
+    compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    /// Estimate of the bytes left to compact, as of the last compaction pick.
+    pending_compaction_bytes: Arc<AtomicU64>,
+    stall_options: WriteStallOptions,
+    stall_stats: Arc<Mutex<WriteStallStats>>,
+    /// Paces the writes of flushes, at `Priority::High`, and of compactions.
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// The time expiring values are set and checked against.
+    clock: Arc<dyn Clock>,
+    /// Held while a compaction picks and replaces tables, one runs at a time.
+    compaction_lock: Arc<Mutex<()>>,
+    /// Runs compactions after flushes. `None` in the handle the worker itself uses.
+    compaction_worker: Option<Arc<Worker>>,
+    /// Writes immutable memtables to sstables. `None` in the handles the workers use.
+    flush_worker: Option<Arc<Worker>>,
+    /// Reads values kept in blob files.
+    blobs: BlobReader,
+    max_key_size: usize,
+    max_value_size: usize,
 }
 
 impl KVStore {
     pub fn new(name: String, size: usize, sstable_dir: PathBuf) -> Self {
+        KVStore::with_options(name, size, sstable_dir, Options::default())
+    }
+
+    pub fn with_options(name: String, size: usize, sstable_dir: PathBuf, options: Options) -> Self {
+        let store_dir = sstable_dir.join(&name).join(RKV);
+        let sync = options.sync_policy();
+        let manifest = match recover_manifest(&sstable_dir, &name, &sync) {
+            Ok(manifest) => manifest,
+            Err(e) => panic!("Failed to open the manifest because {}", e),
+        };
+        let (wal, records) = match WriteAheadLog::open(
+            &store_dir.join("wal"),
+            manifest.log_number(),
+            sync.clone(),
+        ) {
+            Ok(opened) => opened,
+            Err(e) => panic!("Failed to open the write-ahead log because {}", e),
+        };
+        let blobs = BlobReader::new(sstable_data_dir(&sstable_dir, &name));
         let mut store = KVStore {
             name,
             memtable: Arc::new(Mutex::new(BTreeMap::new())),
             mem_size: Arc::new(Mutex::new(0)),
             max_bytes: size,
+            immutables: Arc::new(Mutex::new(vec![])),
+            max_immutables: options.max_immutable_memtables.max(1),
             sstables: Arc::new(Mutex::new(vec![])),
             sstable_dir,
+            next_file_number: Arc::new(AtomicU64::new(manifest.next_file_number())),
+            wal: Arc::new(Mutex::new(wal)),
+            manifest: Arc::new(Mutex::new(manifest)),
+            sync,
+            table_options: options.table,
+            blob_options: options.blob,
+            picker: options.compaction,
+            compaction_filter: options.compaction_filter,
+            pending_compaction_bytes: Arc::new(AtomicU64::new(0)),
+            stall_options: options.stall,
+            stall_stats: Arc::new(Mutex::new(WriteStallStats::default())),
+            rate_limiter: options.rate_limiter,
+            clock: options.clock,
+            compaction_lock: Arc::new(Mutex::new(())),
+            compaction_worker: None,
+            flush_worker: None,
+            blobs,
+            max_key_size: options.max_key_size,
+            max_value_size: options.max_value_size,
+        };
+        store.load_sstables();
+        store.replay(records);
+
+        let mut compactor = store.clone();
+        let compaction_worker =
+            match Worker::spawn(format!("{}-compaction", store.name), move || {
+                compactor.compaction()
+            }) {
+                Ok(worker) => Arc::new(worker),
+                Err(e) => panic!("Failed to start the compaction thread because {}", e),
+            };
+        // A previous run may have stopped with levels over their targets.
+        compaction_worker.schedule();
+        store.compaction_worker = Some(compaction_worker);
+
+        // The flusher keeps the compaction worker, to schedule compactions after its flushes.
+        let mut flusher = store.clone();
+        let flush_worker = match Worker::spawn(format!("{}-flush", store.name), move || {
+            flusher.flush_immutables()
+        }) {
+            Ok(worker) => Arc::new(worker),
+            Err(e) => panic!("Failed to start the flush thread because {}", e),
         };
-        let discovered_tables = store.discover_sstables();
-        store.sstables = Arc::new(Mutex::new(discovered_tables));
+        store.flush_worker = Some(flush_worker);
         store
     }
 
+    /// Rebuild the memtable from write-ahead log records left by a previous run.
+    fn replay(&mut self, records: Vec<LogRecord>) {
+        let mut memtable = self.memtable.lock().unwrap();
+        let mut mem_size = self.mem_size.lock().unwrap();
+        for record in records {
+            match record {
+                LogRecord::Set { key, value } => {
+                    *mem_size += key.len() + value.len();
+                    memtable.insert(key, Value::Put(value));
+                }
+                LogRecord::Delete { key } => {
+                    *mem_size += key.len();
+                    memtable.insert(key, Value::Delete);
+                }
+                LogRecord::SetExpiring {
+                    key,
+                    value,
+                    expires_at,
+                } => {
+                    *mem_size += key.len() + value.len();
+                    memtable.insert(key, Value::Expiring { value, expires_at });
+                }
+            }
+        }
+    }
+
     fn is_overflow(&self) -> bool {
         match self.mem_size.lock() {
             Ok(mem_size) => *mem_size >= self.max_bytes,
@@ -55,6 +203,14 @@ impl KVStore {
         }
     }
 
+    /// Track the number of sstables in `level`, L0 holds the flushed ones.
+    pub fn get_sstables_count_at_level(&self, level: u16) -> usize {
+        match self.sstables.lock() {
+            Ok(sstables) => sstables.iter().filter(|t| t.get_level() == level).count(),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        }
+    }
+
     /// Track the number of sstables.
     pub fn get_sstables_count(&self) -> usize {
         match self.sstables.lock() {
@@ -63,29 +219,43 @@ impl KVStore {
         }
     }
 
-    /// Find sstables after restarts.
+    /// Open the sstables committed to the manifest, oldest first.
     ///
-    /// As long as sstables (.rkv) files are present at the path,
-    /// this method will load them before creating an instance of the `KVStore`.
-    fn discover_sstables(&mut self) -> Vec<SSTable> {
-        let mut sstables: Vec<SSTable> = vec![];
-        let sstable_dir = self.sstable_dir.join(RKV).join("dat");
-        let sstable_dir_str = sstable_dir.as_path().display().to_string();
-        let glob_pattern = format!("{}/*.{}", sstable_dir_str, RKV);
-        for entry in glob(&glob_pattern).expect("Failed to read glob pattern") {
-            match entry {
-                Ok(path) => match SSTable::new(path.clone(), 0, true, true, false) {
-                    Ok(sstable) => sstables.push(sstable),
-                    Err(e) => error!(
-                        "Failed to read sstable {} because {}",
-                        path.as_path().display(),
-                        e
-                    ),
-                },
-                Err(e) => println!("{:?}", e),
-            }
+    /// Any other table or blob file in the data directory was left behind
+    /// by a flush or compaction that did not commit, and is removed. When
+    /// the manifest dropped a torn edit they are kept instead, and new
+    /// files are numbered past them.
+    fn load_sstables(&mut self) {
+        let data_dir = sstable_data_dir(&self.sstable_dir, &self.name);
+        let manifest = self.manifest.lock().unwrap();
+        let tables = manifest.tables();
+        if manifest.dropped_tail() {
+            warn!("The manifest ended in a torn edit, keeping files it does not name");
+            let last = last_file_number(&data_dir);
+            self.next_file_number.fetch_max(last + 1, Ordering::SeqCst);
+        } else {
+            let live: HashSet<u64> = tables.iter().map(|table| table.number).collect();
+            remove_obsolete_sstables(&data_dir, &live);
+            let live_blobs: HashSet<u64> = manifest.blob_files().into_iter().collect();
+            remove_obsolete_blob_files(&data_dir, &live_blobs);
+        }
+        drop(manifest);
+        let mut sstables = vec![];
+        for table in tables {
+            let path = sstable_path(&data_dir, table.level, table.number);
+            sstables.push(SSTable::new(path, table.level));
+        }
+        sort_sstables(&mut sstables);
+        self.sstables = Arc::new(Mutex::new(sstables));
+    }
+
+    /// Append an edit to the manifest, making it survive restarts.
+    fn commit(&self, mut edit: VersionEdit) -> Result<()> {
+        edit.next_file_number = Some(self.next_file_number.load(Ordering::SeqCst));
+        match self.manifest.lock() {
+            Ok(mut manifest) => manifest.log_and_apply(edit),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
         }
-        sstables
     }
 
     /// Reduce number of SSTables.
@@ -99,102 +269,773 @@ impl KVStore {
     /// 1. Keys that are updated frequently.
     /// 1. Keys that have been deleted.
     ///
-    /// These will occupy extra space in multiple sstables. We can periodically clean up and
-    /// combine sstables into single table. Since this process is also slow, we run it on a separate thread.
-    pub fn compaction(&mut self) {
-        self.sstables = sstable_compaction(
-            self.sstables.clone(),
-            self.name.clone(),
-            self.get_last_sstable_level(),
-            &self.sstable_dir,
-        );
+    /// These will occupy extra space in multiple sstables. This runs the compactions
+    /// `Options::compaction` picks until it has none left, by default `LeveledCompaction`
+    /// which keeps tables in levels so each compaction only rewrites a slice of the data.
+    ///
+    /// Flushes leave this to a background thread, see `wait_for_compaction`. Calling it
+    /// directly runs the compactions on the calling thread, one at a time with the
+    /// background ones.
+    ///
+    /// The merged tables replace their inputs in a single manifest edit, and in the
+    /// table set readers take their snapshots of. The inputs are only deleted once
+    /// no lookup or iterator reads them anymore. Blob files no table refers to
+    /// anymore go in the same edit. Values in blob files are not rewritten, except
+    /// for the oldest files picked by `BlobOptions::gc_age_cutoff`.
+    pub fn compaction(&mut self) -> Result<()> {
+        let lock = self.compaction_lock.clone();
+        let _running = match lock.lock() {
+            Ok(running) => running,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        loop {
+            let sstables = match self.sstables.lock() {
+                Ok(sstables) => sstables.clone(),
+                Err(e) => panic!("Failed to lock. Reason: {}", e),
+            };
+            let pending = self.picker.pending_bytes(&sstables)?;
+            self.pending_compaction_bytes
+                .store(pending, Ordering::SeqCst);
+            match self.picker.pick(&sstables)? {
+                Some(compaction) => self.run_compaction(compaction)?,
+                None => return Ok(()),
+            }
+        }
     }
 
-    fn get_last_sstable_level(&self) -> u16 {
-        match self.sstables.lock() {
-            Ok(sstables) => {
-                if sstables.is_empty() {
-                    0
-                } else {
-                    match sstables.last() {
-                        Some(sstable) => sstable.get_level(),
-                        None => 0,
+    /// Merge every table holding keys from `start` up to, not including, `end`
+    /// into the bottom of the store, dropping overwritten values and deletes.
+    ///
+    /// This reclaims the space of keys deleted in bulk, such as one tenant's,
+    /// which a compaction might otherwise not get to for a long time. The
+    /// memtable is flushed first. With `LeveledCompaction` the keys end up in
+    /// the last level, other strategies decide through
+    /// `CompactionStrategy::compact_range`.
+    ///
+    /// Tables are added until none outside the compaction holds any of its
+    /// keys, so it may cover more than the range.
+    pub fn compact_range(&mut self, start: &[u8], end: &[u8]) -> Result<()> {
+        if start >= end {
+            return Ok(());
+        }
+        self.flush_memtable()?;
+        let lock = self.compaction_lock.clone();
+        let _running = match lock.lock() {
+            Ok(running) => running,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        let sstables = match self.sstables.lock() {
+            Ok(sstables) => sstables.clone(),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        let mut ranges = vec![];
+        for sstable in &sstables {
+            ranges.push(sstable.key_range()?);
+        }
+        // Grow the range until it takes in every table it overlaps.
+        let mut low = start.to_vec();
+        let mut high = end.to_vec();
+        let mut included = vec![false; sstables.len()];
+        let mut high_included = false;
+        loop {
+            let mut grown = false;
+            for (i, range) in ranges.iter().enumerate() {
+                let overlaps = match range {
+                    Some((smallest, largest)) => {
+                        (smallest < &high || (high_included && smallest == &high))
+                            && largest >= &low
+                    }
+                    None => false,
+                };
+                if overlaps && !included[i] {
+                    included[i] = true;
+                    grown = true;
+                    if let Some((smallest, largest)) = range {
+                        low = low.min(smallest.clone());
+                        if largest >= &high {
+                            high = largest.clone();
+                            high_included = true;
+                        }
                     }
                 }
             }
+            if !grown {
+                break;
+            }
+        }
+        let inputs: Vec<SSTable> = sstables
+            .into_iter()
+            .zip(included)
+            .filter(|(_, included)| *included)
+            .map(|(sstable, _)| sstable)
+            .collect();
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        // No table left out holds keys of the inputs, so every delete is dropped.
+        let compaction = self.picker.compact_range(inputs);
+        self.run_compaction(compaction)
+    }
+
+    fn run_compaction(&mut self, compaction: Compaction) -> Result<()> {
+        let data_dir = sstable_data_dir(&self.sstable_dir, &self.name);
+        let mut blobs = BlobGc::new(
+            data_dir.clone(),
+            self.blob_gc_cutoff(),
+            self.next_file_number.clone(),
+        );
+        if let Some(limiter) = &self.rate_limiter {
+            blobs.set_rate_limiter(limiter.clone());
+        }
+        let outputs = if compaction.drop_inputs {
+            debug!("Dropping {} tables", compaction.inputs.len());
+            vec![]
+        } else {
+            debug!(
+                "Compacting {} tables from L{} into L{}",
+                compaction.inputs.len(),
+                compaction.level,
+                compaction.output_level
+            );
+            let older = self.older_key_ranges(&compaction)?;
+            sstable_compaction(
+                &compaction,
+                &older,
+                self.compaction_filter.as_deref(),
+                &self.name,
+                &self.sstable_dir,
+                &self.next_file_number,
+                &self.sync,
+                &self.table_options,
+                &mut blobs,
+                self.rate_limiter.as_ref(),
+                self.clock.now_millis(),
+            )?
+        };
+        let inputs = compaction.inputs;
+        let relocated = blobs.finish(&self.sync)?;
+        let input_numbers: HashSet<u64> = inputs.iter().map(|t| t.get_number()).collect();
+
+        // Blob files the inputs referred to may now be garbage. Tables
+        // flushed meanwhile only refer to blob files of their own.
+        let mut obsolete_blobs: HashSet<u64> = relocated.into_iter().collect();
+        for sstable in &inputs {
+            obsolete_blobs.extend(sstable.blob_files()?);
+        }
+        let remaining = self.sstables.lock().unwrap().clone();
+        for sstable in outputs.iter().chain(
+            remaining
+                .iter()
+                .filter(|t| !input_numbers.contains(&t.get_number())),
+        ) {
+            for number in sstable.blob_files()? {
+                obsolete_blobs.remove(&number);
+            }
+        }
+
+        self.commit(VersionEdit {
+            new_tables: outputs
+                .iter()
+                .map(|t| TableMeta {
+                    number: t.get_number(),
+                    level: t.get_level(),
+                })
+                .collect(),
+            deleted_tables: inputs.iter().map(|t| t.get_number()).collect(),
+            new_blob_files: relocated.into_iter().collect(),
+            deleted_blob_files: obsolete_blobs.iter().copied().collect(),
+            ..Default::default()
+        })?;
+
+        match self.sstables.lock() {
+            Ok(mut sstables) => {
+                sstables.retain(|t| !input_numbers.contains(&t.get_number()));
+                sstables.extend(outputs);
+                sort_sstables(&mut sstables);
+            }
             Err(e) => panic!("Failed to lock. Reason: {}", e),
         }
+        let mut obsolete: Vec<PathBuf> = inputs.iter().map(|t| t.get_path().to_owned()).collect();
+        obsolete.extend(obsolete_blobs.iter().map(|n| blob_path(&data_dir, *n)));
+        let obsolete = ObsoleteFiles::new(obsolete);
+        for sstable in &inputs {
+            sstable.delete_when_unused(obsolete.clone());
+        }
+        self.sync.on_dir_change(&data_dir)
+    }
+
+    /// Block until the memtables frozen so far are flushed, and the compactions due
+    /// after those flushes are done.
+    ///
+    /// Fails with the error of a background flush or compaction that failed since the
+    /// last call.
+    pub fn wait_for_compaction(&self) -> Result<()> {
+        self.wait_for_flush()?;
+        match &self.compaction_worker {
+            Some(worker) => worker.wait(),
+            None => Ok(()),
+        }
+    }
+
+    /// Key ranges of the tables that are not merged by `compaction` but are older
+    /// than its outputs: the deeper levels, and for outputs in L0 the tables
+    /// flushed before the inputs. Deletes of keys outside them are dropped.
+    fn older_key_ranges(&self, compaction: &Compaction) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut inputs = HashSet::new();
+        let mut flush_number = 0;
+        for sstable in &compaction.inputs {
+            inputs.insert(sstable.get_number());
+            flush_number = flush_number.max(sstable.flush_number()?);
+        }
+        let sstables = match self.sstables.lock() {
+            Ok(sstables) => sstables.clone(),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        let output_level = compaction.output_level;
+        let mut ranges = vec![];
+        for sstable in sstables {
+            if inputs.contains(&sstable.get_number()) {
+                continue;
+            }
+            let older = match sstable.get_level() {
+                level if level > output_level => true,
+                0 if output_level == 0 => sstable.flush_number()? < flush_number,
+                _ => false,
+            };
+            if older {
+                ranges.extend(sstable.key_range()?);
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Blob files numbered below this have their values moved by the next compaction.
+    fn blob_gc_cutoff(&self) -> u64 {
+        let blob_files = self.manifest.lock().unwrap().blob_files();
+        let collected = (blob_files.len() as f64 * self.blob_options.gc_age_cutoff) as usize;
+        match collected.min(blob_files.len()).checked_sub(1) {
+            Some(last) => blob_files[last] + 1,
+            None => 0,
+        }
     }
 
     /// Drain key-value pairs into an sstable.
+    ///
+    /// Freezes the memtable, then waits until it and every other immutable memtable
+    /// is written out by the flush thread.
     pub fn flush_memtable(&mut self) -> Result<()> {
-        let mut sstable = create_sstable(
-            self.get_last_sstable_level(),
+        self.make_room()?;
+        self.wait_for_flush()
+    }
+
+    /**
+     * Make the memtable immutable and start a new one, with a new write-ahead log.
+     *
+     * The flush thread writes immutable memtables out in the background, and
+     * lookups read them until then:
+     *
+     *  set -> | memtable | -> | immutable | immutable | -> flush -> | L0 sstable |
+     *           ^ new writes     ^ still searched by get and scans
+     *
+     * Once `Options::max_immutable_memtables` are waiting, writers stall here
+     * until a flush makes room, and fail with its error if it fails.
+     */
+    fn make_room(&mut self) -> Result<()> {
+        loop {
+            let frozen = match self.wal.lock() {
+                Ok(mut wal) => self.freeze(&mut wal)?,
+                Err(e) => panic!("Failed to lock. Reason: {}", e),
+            };
+            self.schedule_flush()?;
+            if frozen {
+                return Ok(());
+            }
+            debug!("Too many immutable memtables. Waiting for a flush");
+            let started = Instant::now();
+            let flushed = self.wait_for_flush();
+            self.record_stall(Stall::Stop, started);
+            flushed?;
+        }
+    }
+
+    /// What the backlog of flushes and compactions does to the next write.
+    fn stall(&self) -> Stall {
+        let limits = &self.stall_options;
+        let immutables = self.immutables.lock().unwrap().len();
+        let level0 = match self.picker.stalls_on_level0() {
+            true => self.get_sstables_count_at_level(0),
+            false => 0,
+        };
+        let pending = self.pending_compaction_bytes.load(Ordering::SeqCst);
+        if level0 >= limits.level0_stop_trigger || pending >= limits.pending_compaction_bytes_stop {
+            Stall::Stop
+        } else if immutables >= limits.immutable_memtables_slowdown
+            || level0 >= limits.level0_slowdown_trigger
+            || pending >= limits.pending_compaction_bytes_slowdown
+        {
+            Stall::Slowdown
+        } else {
+            Stall::None
+        }
+    }
+
+    /// Hold a write back while flushes and compactions are behind, see `WriteStallOptions`.
+    fn throttle(&self) -> Result<()> {
+        let started = Instant::now();
+        match self.stall() {
+            Stall::None => {}
+            Stall::Slowdown => {
+                thread::sleep(self.stall_options.slowdown_delay);
+                self.record_stall(Stall::Slowdown, started);
+            }
+            Stall::Stop => {
+                debug!("Too much background work pending. Waiting for it");
+                let caught_up = self.wait_for_compaction();
+                self.record_stall(Stall::Stop, started);
+                caught_up?;
+            }
+        }
+        Ok(())
+    }
+
+    fn record_stall(&self, stall: Stall, started: Instant) {
+        let mut stats = match self.stall_stats.lock() {
+            Ok(stats) => stats,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        match stall {
+            Stall::None => {}
+            Stall::Slowdown => {
+                stats.slowdowns += 1;
+                stats.slowdown_time += started.elapsed();
+            }
+            Stall::Stop => {
+                stats.stops += 1;
+                stats.stop_time += started.elapsed();
+            }
+        }
+    }
+
+    /// How often and how long `set` and `delete` were held back for background work.
+    pub fn stall_stats(&self) -> WriteStallStats {
+        match self.stall_stats.lock() {
+            Ok(stats) => *stats,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        }
+    }
+
+    /// Move the memtable to the immutable ones, `false` if they are full.
+    ///
+    /// Takes the write-ahead log so that no write slips in between the logs.
+    fn freeze(&self, wal: &mut WriteAheadLog) -> Result<bool> {
+        let mut memtable = self.memtable.lock().unwrap();
+        if memtable.is_empty() {
+            return Ok(true);
+        }
+        let mut immutables = self.immutables.lock().unwrap();
+        if immutables.len() >= self.max_immutables {
+            return Ok(false);
+        }
+        let next_log_number = wal.rotate()?;
+        immutables.push(Arc::new(ImmutableMemtable {
+            entries: std::mem::take(&mut *memtable),
+            next_log_number,
+        }));
+        *self.mem_size.lock().unwrap() = 0;
+        Ok(true)
+    }
+
+    fn schedule_flush(&mut self) -> Result<()> {
+        match &self.flush_worker {
+            Some(worker) => {
+                worker.schedule();
+                Ok(())
+            }
+            None => self.flush_immutables(),
+        }
+    }
+
+    fn wait_for_flush(&self) -> Result<()> {
+        match &self.flush_worker {
+            Some(worker) => worker.wait(),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the immutable memtables to sstables, oldest first.
+    fn flush_immutables(&mut self) -> Result<()> {
+        loop {
+            let immutable = match self.immutables.lock().unwrap().first() {
+                Some(immutable) => immutable.clone(),
+                None => return Ok(()),
+            };
+            self.flush_immutable(&immutable)?;
+        }
+    }
+
+    /// Write `immutable` to a new L0 sstable.
+    ///
+    /// It is replaced by the sstable once that is committed, after which the
+    /// write-ahead logs that covered it are discarded.
+    fn flush_immutable(&mut self, immutable: &ImmutableMemtable) -> Result<()> {
+        let sstable = create_sstable(
+            0,
+            self.next_file_number.fetch_add(1, Ordering::SeqCst),
             self.name.clone(),
             &self.sstable_dir,
         );
-        sstable.write(&self.memtable.lock().unwrap())?;
+        let blob_file = self.write_memtable(&sstable, &immutable.entries)?;
+        sstable.sync(&self.sync)?;
+        self.commit(VersionEdit {
+            log_number: Some(immutable.next_log_number),
+            new_tables: vec![TableMeta {
+                number: sstable.get_number(),
+                level: sstable.get_level(),
+            }],
+            new_blob_files: blob_file.into_iter().collect(),
+            ..Default::default()
+        })?;
+        // Readers look at immutable memtables before sstables, so the sstable goes in first.
         match self.sstables.lock() {
             Ok(mut sstables) => sstables.push(sstable),
             Err(e) => panic!("Failed to lock. Reason: {}", e),
         }
+        self.immutables.lock().unwrap().remove(0);
+        match self.wal.lock() {
+            Ok(wal) => wal.remove_obsolete(immutable.next_log_number)?,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        }
+        match &self.compaction_worker {
+            Some(worker) => {
+                worker.schedule();
+                Ok(())
+            }
+            None => self.compaction(),
+        }
+    }
+
+    /// Write `memtable` to `sstable`, moving values of at least
+    /// `BlobOptions::min_blob_size` to a new blob file. Returns the number
+    /// of the blob file, if one was needed.
+    fn write_memtable(
+        &self,
+        sstable: &SSTable,
+        memtable: &BTreeMap<Vec<u8>, Value>,
+    ) -> Result<Option<u64>> {
+        let mut builder = sstable.builder(&self.table_options)?;
+        if let Some(limiter) = &self.rate_limiter {
+            builder.set_rate_limiter(limiter.clone(), Priority::High);
+        }
+        let mut blobs: Option<BlobWriter> = None;
+        for (key, value) in memtable.iter() {
+            match (value, self.blob_options.min_blob_size) {
+                (Value::Put(contents), Some(min_blob_size)) if contents.len() >= min_blob_size => {
+                    let writer = match &mut blobs {
+                        Some(writer) => writer,
+                        None => {
+                            let mut writer = BlobWriter::create(
+                                &sstable_data_dir(&self.sstable_dir, &self.name),
+                                self.next_file_number.fetch_add(1, Ordering::SeqCst),
+                            )?;
+                            if let Some(limiter) = &self.rate_limiter {
+                                writer.set_rate_limiter(limiter.clone(), Priority::High);
+                            }
+                            blobs.insert(writer)
+                        }
+                    };
+                    builder.add(key, &Value::Blob(writer.add(key, contents)?))?;
+                }
+                _ => builder.add(key, value)?,
+            }
+        }
+        builder.finish()?;
+        match blobs {
+            Some(writer) => writer.finish(&self.sync).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Record a mutation in the write-ahead log, then apply it to the memtable.
+    fn apply(&mut self, record: LogRecord) -> Result<()> {
+        let mut wal = match self.wal.lock() {
+            Ok(wal) => wal,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        wal.append(&record)?;
+        let (k, v) = match record {
+            LogRecord::Set { key, value } => (key, Value::Put(value)),
+            LogRecord::Delete { key } => (key, Value::Delete),
+            LogRecord::SetExpiring {
+                key,
+                value,
+                expires_at,
+            } => (key, Value::Expiring { value, expires_at }),
+        };
+        match self.memtable.lock() {
+            Ok(mut memtable) => memtable.insert(k, v),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        Ok(())
+    }
 
-        if self.get_sstables_count() > 1 {
-            self.compaction();
+    /// Reject keys and values above the sizes configured in `Options`.
+    fn check_size(&self, what: &str, size: usize, max_size: usize) -> Result<()> {
+        if size > max_size {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} of {} bytes exceeds the limit of {} bytes",
+                    what, size, max_size
+                ),
+            ));
         }
-        self.memtable = Arc::new(Mutex::new(BTreeMap::new()));
-        self.mem_size = Arc::new(Mutex::new(0));
         Ok(())
     }
 
     /// Set a key value pair in the store.
-    pub fn set(&mut self, k: &[u8], v: &[u8]) {
+    ///
+    /// Fails with an `InvalidInput` error when the key or value is larger than
+    /// `Options::max_key_size` or `Options::max_value_size`, and with the
+    /// underlying error when the write-ahead log or a flush fails.
+    ///
+    /// Slows down or waits while flushes and compactions are behind, see
+    /// `WriteStallOptions`.
+    pub fn set(&mut self, k: &[u8], v: &[u8]) -> Result<()> {
+        self.put(k, v, None)
+    }
+
+    /// Set a key value pair that reads as deleted once `ttl` has passed.
+    ///
+    /// The deadline is on `Options::clock`. `get`, and scans and cursors
+    /// created from then on, stop returning the value at its deadline, and
+    /// the first compaction of its table after that removes it. Setting the
+    /// key again, with or without a TTL, replaces the deadline. Values set
+    /// with a TTL stay in the sstables, whatever `BlobOptions::min_blob_size`.
+    ///
+    /// Fails like `set`.
+    pub fn set_with_ttl(&mut self, k: &[u8], v: &[u8], ttl: Duration) -> Result<()> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        self.put(k, v, Some(self.clock.now_millis().saturating_add(ttl)))
+    }
+
+    fn put(&mut self, k: &[u8], v: &[u8], expires_at: Option<u64>) -> Result<()> {
+        self.check_size("key", k.len(), self.max_key_size)?;
+        self.check_size("value", v.len(), self.max_value_size)?;
+        self.throttle()?;
         match self.mem_size.lock() {
             Ok(mut mem_size) => *mem_size += k.len() + v.len(),
             Err(e) => panic!("Failed to lock. Reason: {}", e),
         }
         if self.is_overflow() {
-            debug!("Memtable is full. Flushing to disk");
-
-            if let Err(e) = self.flush_memtable() {
-                panic!("Failed to flush memtable because {}", e);
-            }
+            debug!("Memtable is full. Freezing it");
+            self.make_room()?;
         }
-        match self.memtable.lock() {
-            Ok(mut memtable) => memtable.insert(k.to_vec(), v.to_vec()),
+        let (key, value) = (k.to_vec(), v.to_vec());
+        self.apply(match expires_at {
+            Some(expires_at) => LogRecord::SetExpiring {
+                key,
+                value,
+                expires_at,
+            },
+            None => LogRecord::Set { key, value },
+        })
+    }
+
+    /// Get the value for a key stored previously
+    ///
+    /// Fails with an `InvalidData` error when an sstable that has to be read is corrupted.
+    pub fn get(&mut self, k: &[u8]) -> Result<Option<Vec<u8>>> {
+        let found = match self.memtable.lock() {
+            Ok(memtable) => memtable.get(k).cloned(),
             Err(e) => panic!("Failed to lock. Reason: {}", e),
         };
+        let found = match found {
+            Some(value) => Some(value),
+            None => self
+                .immutables()
+                .iter()
+                .rev()
+                .find_map(|immutable| immutable.entries.get(k).cloned()),
+        };
+        // Holding the tables until the value is resolved keeps the blob files they refer to.
+        let sstables = Arc::new(self.sstables.lock().unwrap().clone());
+        let found = match found {
+            Some(value) => Some(value),
+            None => parallel_search(sstables.clone(), k.to_vec())?,
+        };
+        match found {
+            Some(value) => self.blobs.resolve(k, value, self.clock.now_millis()),
+            None => Ok(None),
+        }
     }
 
-    /// Get the value for a key stored previously
-    pub fn get(&mut self, k: &[u8]) -> Option<Vec<u8>> {
-        match self.memtable.lock() {
-            Ok(memtable) => {
-                if let Some(v) = memtable.get(k) {
-                    if v == TOMBSTONE {
-                        return None;
-                    }
-                    return Some(v.to_vec());
-                }
-            }
+    /// Iterate over the key value pairs with keys in `range`, in key order.
+    ///
+    /// The most recent value of each key is returned, deleted keys are
+    /// skipped. Errors reading an sstable end the iteration.
+    ///
+    /// # Example
+    /// ```
+    /// use rkv::store::lsm_store::KVStore;
+    ///
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let mut store = KVStore::new("books".to_owned(), 100, dir.path().to_path_buf());
+    /// store.set(b"book:1", b"Dune").unwrap();
+    /// store.set(b"book:2", b"Emma").unwrap();
+    /// store.set(b"film:1", b"Heat").unwrap();
+    /// let books: Vec<_> = store
+    ///     .scan(b"book:".to_vec()..b"book;".to_vec())
+    ///     .unwrap()
+    ///     .map(|pair| pair.unwrap().1)
+    ///     .collect();
+    /// assert_eq!(books, vec![b"Dune".to_vec(), b"Emma".to_vec()]);
+    /// ```
+    pub fn scan<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Result<StoreIterator> {
+        self.scan_tables(range, |_| Ok(true))
+    }
+
+    /// Iterate over the key value pairs whose key starts with `prefix`, in key order.
+    ///
+    /// With a `prefix_extractor` in the table options, sstables whose bloom
+    /// filter rules out `prefix` are skipped. That only works when `prefix`
+    /// is a whole prefix as the extractor sees it, such as `tenant/` for
+    /// `DelimitedPrefix(b'/')`, other prefixes read every table.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<StoreIterator> {
+        let start = Bound::Included(prefix.to_vec());
+        let end = match prefix_successor(prefix) {
+            Some(successor) => Bound::Excluded(successor),
+            None => Bound::Unbounded,
+        };
+        match &self.table_options.prefix_extractor {
+            Some(extractor) if extractor.prefix(prefix) == Some(prefix) => self
+                .scan_tables((start, end), |sstable| {
+                    sstable.may_contain_prefix(prefix, extractor.as_ref())
+                }),
+            _ => self.scan_tables((start, end), |_| Ok(true)),
+        }
+    }
+
+    /// Merge the memtable with the sstables `include` accepts.
+    fn scan_tables<R, F>(&self, range: R, include: F) -> Result<StoreIterator>
+    where
+        R: RangeBounds<Vec<u8>>,
+        F: Fn(&SSTable) -> Result<bool>,
+    {
+        if is_empty_range(&range) {
+            return Ok(StoreIterator::new(
+                MergingIterator::new(vec![]),
+                &range,
+                self.blobs.clone(),
+                self.clock.now_millis(),
+            ));
+        }
+        let memtable: Vec<(Vec<u8>, Value)> = match self.memtable.lock() {
+            Ok(memtable) => memtable
+                .range::<Vec<u8>, _>((range.start_bound(), range.end_bound()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        let immutables = self.immutables();
+        let sstables = match self.sstables.lock() {
+            Ok(sstables) => sstables.clone(),
             Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        let mut sources: Vec<KVIterator> = vec![];
+        for sstable in sstables {
+            if !include(&sstable)? {
+                continue;
+            }
+            let entries = match range.start_bound() {
+                Bound::Included(start) | Bound::Excluded(start) => sstable.iter_from(start)?,
+                Bound::Unbounded => sstable.iter()?,
+            };
+            sources.push(Box::new(entries));
+        }
+        for immutable in immutables {
+            let entries: Vec<(Vec<u8>, Value)> = immutable
+                .entries
+                .range::<Vec<u8>, _>((range.start_bound(), range.end_bound()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            sources.push(Box::new(entries.into_iter().map(Ok)));
         }
-        parallel_search(self.sstables.clone(), k.to_vec())
+        sources.push(Box::new(memtable.into_iter().map(Ok)));
+        Ok(StoreIterator::new(
+            MergingIterator::new(sources),
+            &range,
+            self.blobs.clone(),
+            self.clock.now_millis(),
+        ))
     }
 
-    /// Remove a key value pair.
-    pub fn delete(&mut self, k: &[u8]) {
-        match self.memtable.lock() {
-            Ok(mut memtable) => memtable.insert(k.to_vec(), TOMBSTONE.to_vec()),
+    /// A cursor over the store that can seek and move in both directions, see `StoreCursor`.
+    pub fn cursor(&self) -> Result<StoreCursor> {
+        let memtable: Vec<(Vec<u8>, Value)> = match self.memtable.lock() {
+            Ok(memtable) => memtable
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
             Err(e) => panic!("Failed to lock. Reason: {}", e),
         };
+        let immutables = self.immutables();
+        let sstables = match self.sstables.lock() {
+            Ok(sstables) => sstables.clone(),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        };
+        let mut children: Vec<Box<dyn Cursor>> = vec![];
+        for sstable in sstables {
+            children.push(Box::new(sstable.cursor()?));
+        }
+        for immutable in immutables {
+            let entries = immutable
+                .entries
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            children.push(Box::new(MemCursor::new(entries)));
+        }
+        children.push(Box::new(MemCursor::new(memtable)));
+        Ok(StoreCursor::new(
+            children,
+            self.blobs.clone(),
+            self.clock.now_millis(),
+        ))
+    }
+
+    /// Iterate over all key value pairs in key order.
+    pub fn iter(&self) -> Result<StoreIterator> {
+        self.scan(..)
+    }
+
+    /// Remove a key value pair.
+    ///
+    /// Fails like `set` for keys that are too large or when the write-ahead log fails.
+    pub fn delete(&mut self, k: &[u8]) -> Result<()> {
+        self.check_size("key", k.len(), self.max_key_size)?;
+        self.throttle()?;
+        self.apply(LogRecord::Delete { key: k.to_vec() })?;
 
-        if parallel_search(self.sstables.clone(), k.to_vec()).is_some() {
+        if let Ok(Some(Value::Put(_) | Value::Blob(_) | Value::Expiring { .. })) =
+            parallel_search(Arc::new(self.sstables.lock().unwrap().clone()), k.to_vec())
+        {
             match self.mem_size.lock() {
                 Ok(mut mem_size) => *mem_size += k.len(),
                 Err(e) => panic!("Failed to lock. Reason: {}", e),
             }
         }
+        Ok(())
+    }
+
+    /// Immutable memtables waiting to be flushed, oldest first.
+    ///
+    /// Take this after reading the memtable and before the sstables: a memtable
+    /// frozen or flushed in between is then still seen once.
+    fn immutables(&self) -> Vec<Arc<ImmutableMemtable>> {
+        match self.immutables.lock() {
+            Ok(immutables) => immutables.clone(),
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        }
     }
 
     /// Get the current size of memtable.
@@ -206,63 +1047,143 @@ impl KVStore {
     }
 }
 
+/// A full memtable, only read until the flush thread writes it to an sstable.
+struct ImmutableMemtable {
+    entries: BTreeMap<Vec<u8>, Value>,
+    /// The write-ahead log started when it was frozen, older logs only hold its entries.
+    next_log_number: u64,
+}
+
 /// Parallel search SSTables.
 ///
 /// sstables=Vec<SSTables> is ordered such that the most recent table is at the end.
 /// 1. We partition sstables so that multiple threads can search them in parallel.
-/// 2. We use a channel to collect results from each thread.
-fn parallel_search(shared_sstables: Arc<Mutex<Vec<SSTable>>>, k: Vec<u8>) -> Option<Vec<u8>> {
-    let n_sstables = shared_sstables.lock().unwrap().len();
+/// 2. Each thread searches its partition from the most recent table backwards and stops at the
+///    first table that has the key, or fails to read.
+/// 3. Partitions follow the order of the tables, so the outcome of the last partition that
+///    found anything wins. A `Value::Delete` means the key was deleted.
+fn parallel_search(sstables: Arc<Vec<SSTable>>, k: Vec<u8>) -> Result<Option<Value>> {
+    let n_sstables = sstables.len();
+    if n_sstables == 0 {
+        return Ok(None);
+    }
     let n_threads = std::cmp::min(n_sstables, 10);
-    let chunk_size = (n_sstables + n_threads - 1) / n_threads;
+    let chunk_size = n_sstables.div_ceil(n_threads);
     let key = Arc::new(k);
-    let result: Arc<Mutex<Option<Vec<u8>>>> = Arc::new(Mutex::new(None));
     let mut handles = vec![];
-    let last_index: Arc<Mutex<Option<usize>>> = Arc::new(Mutex::new(None));
 
     for i in 0..n_threads {
-        let sstables_locked = shared_sstables.clone();
+        let sstables = sstables.clone();
         let key = key.clone();
-        let result = result.clone();
-        let last_index = last_index.clone();
 
-        let start = i * chunk_size;
+        let start = std::cmp::min(i * chunk_size, n_sstables);
         let end = std::cmp::min(start + chunk_size, n_sstables);
 
         let handle = thread::spawn(move || {
-            let sstables = sstables_locked.lock().unwrap();
-            let sstable_chunk = &sstables[start..end];
-            for (j, sstable) in sstable_chunk.iter().enumerate() {
-                let mut current_last_index = last_index.lock().unwrap();
-                if let Some(last_index) = *current_last_index {
-                    if last_index >= start + j {
-                        return;
-                    }
-                }
-
-                let value = match sstable.search(&key) {
-                    Ok(v) => v,
-                    _ => None,
-                };
-
-                if let Some(v) = value {
-                    if v == TOMBSTONE {
-                        return;
-                    }
-                    let mut result = result.lock().unwrap();
-                    *result = Some(v);
-                    *current_last_index = Some(start + j);
-                    return;
+            for sstable in sstables[start..end].iter().rev() {
+                match sstable.search(&key) {
+                    Ok(None) => continue,
+                    outcome => return Some(outcome),
                 }
             }
+            None
         });
         handles.push(handle);
     }
 
+    let mut result = Ok(None);
     for handle in handles {
-        handle.join().expect("Failed to join thread!");
+        if let Some(outcome) = handle.join().expect("Failed to join thread!") {
+            result = outcome;
+        }
+    }
+    result
+}
+
+/**
+ * Order tables so that more recent writes come later: deeper levels first,
+ * then by file number. Tables of a level below L0 do not overlap, so their
+ * order does not matter.
+ *
+ * Tables merged within L0 are only as recent as the flushes they hold, and
+ * sort by `SSTable::flush_number` instead, see `SSTable::recency`.
+ */
+fn sort_sstables(sstables: &mut [SSTable]) {
+    sstables.sort_by_cached_key(|sstable| (Reverse(sstable.get_level()), sstable.recency()));
+}
+
+/// The smallest key greater than every key starting with `prefix`, `None` if there is none.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last < u8::MAX {
+            successor.push(last + 1);
+            return Some(successor);
+        }
+    }
+    None
+}
+
+/// `true` for ranges that end before they start, which hold no keys.
+fn is_empty_range<R: RangeBounds<Vec<u8>>>(range: &R) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/**
+ * Open the manifest of the store `name`.
+ *
+ * Directories written before the manifest existed are not supported:
+ * their tables are named after a uuid, so neither their recency nor a
+ * file number can be recovered, and their layout predates the one read
+ * here. Opening one fails instead of deleting its tables as unknown.
+ */
+fn recover_manifest(sstable_dir: &Path, name: &str, sync: &SyncPolicy) -> Result<Manifest> {
+    let store_dir = sstable_dir.join(name).join(RKV);
+    let data_dir = sstable_data_dir(sstable_dir, name);
+    if !Manifest::exists(&store_dir) && has_sstable_files(&data_dir)? {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} holds sstables but no manifest, older stores are not supported",
+                data_dir.display()
+            ),
+        ));
     }
+    let manifest = Manifest::open(&store_dir, sync.clone())?;
+    Ok(manifest)
+}
 
-    let result = result.lock().unwrap();
-    result.clone()
+/// The largest number of a table or blob file in `data_dir`, `0` if there are none.
+fn last_file_number(data_dir: &Path) -> u64 {
+    let mut last = 0;
+    if let Ok(entries) = read_dir(data_dir) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let number = parse_sstable_name(&path)
+                .map(|(_, number)| number)
+                .or_else(|| parse_blob_name(&path));
+            last = last.max(number.unwrap_or(0));
+        }
+    }
+    last
+}
+
+/// `true` if `data_dir` holds any `.rkv` file, whatever its name.
+fn has_sstable_files(data_dir: &Path) -> Result<bool> {
+    let entries = match read_dir(data_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        if entry?.path().extension().is_some_and(|ext| ext == RKV) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
 }
@@ -1,3 +1,7 @@
+pub mod cursor;
+pub mod iterator;
 pub mod lsm_store;
+pub mod options;
+pub mod stall;
 #[cfg(test)]
 mod store_test;
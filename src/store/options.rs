@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+pub use crate::compaction::fifo::FifoCompaction;
+pub use crate::compaction::filter::{CompactionFilter, Decision};
+pub use crate::compaction::leveled::LeveledCompaction;
+pub use crate::compaction::strategy::CompactionStrategy;
+pub use crate::compaction::tiered::SizeTieredCompaction;
+pub use crate::sstable::blob::BlobOptions;
+pub use crate::sstable::compression::Compression;
+pub use crate::sstable::filter::{DelimitedPrefix, FixedPrefix, PrefixExtractor};
+pub use crate::sstable::sst::TableOptions;
+pub use crate::store::stall::WriteStallOptions;
+pub use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::fs::SyncPolicy;
+pub use crate::utils::fs::{Durability, FileSystem, OsFileSystem};
+pub use crate::utils::rate_limiter::{Priority, RateLimiter};
+
+/// Settings for a `KVStore`.
+///
+/// # Example
+/// ```
+/// use rkv::store::options::{Durability, Options};
+///
+/// let options = Options {
+///     durability: Durability::EveryWrite,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone)]
+pub struct Options {
+    pub durability: Durability,
+    /// Where syncs and renames go, swapped out by tests to simulate crashes.
+    pub fs: Arc<dyn FileSystem>,
+    /// The time values written with `KVStore::set_with_ttl` expire on.
+    pub clock: Arc<dyn Clock>,
+    /// Layout of the sstables written by flushes and compactions.
+    pub table: TableOptions,
+    /// Moving large values out of the sstables, see `BlobOptions`.
+    pub blob: BlobOptions,
+    /// Which tables are compacted and when, `LeveledCompaction` unless set.
+    pub compaction: Arc<dyn CompactionStrategy>,
+    /// Custom logic run over the entries compactions write.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    /// Full memtables that may wait for the flush thread before writes stop.
+    pub max_immutable_memtables: usize,
+    /// When writes are slowed down or stopped for background work to catch up.
+    pub stall: WriteStallOptions,
+    /// Bounds the bytes flushes and compactions write per second, `None` lets them write freely.
+    ///
+    /// To change the rate while the store runs, keep a clone of the `Arc`
+    /// before opening the store and call `RateLimiter::set_bytes_per_second`
+    /// on it. A store opened without a limiter can't be given one later.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Writes with a larger key fail with `InvalidInput`.
+    pub max_key_size: usize,
+    /// Writes with a larger value fail with `InvalidInput`.
+    pub max_value_size: usize,
+}
+
+impl Options {
+    pub(crate) fn sync_policy(&self) -> SyncPolicy {
+        SyncPolicy::new(self.durability, self.fs.clone())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            durability: Durability::OnFlush,
+            fs: Arc::new(OsFileSystem),
+            clock: Arc::new(SystemClock),
+            table: TableOptions::default(),
+            blob: BlobOptions::default(),
+            compaction: Arc::new(LeveledCompaction::default()),
+            compaction_filter: None,
+            max_immutable_memtables: 2,
+            stall: WriteStallOptions::default(),
+            rate_limiter: None,
+            max_key_size: 1 << 20,
+            max_value_size: 1 << 30,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/**
+ * Limits on background work past which writes are held back.
+ *
+ * Flushes and compactions run behind the writers. When they fall too far
+ * behind, L0 piles up and every lookup reads more tables, so `set` and
+ * `delete` are slowed down first and stopped after:
+ *
+ * |<- full speed ->|<- sleep slowdown_delay ->|<- wait for the background threads ->
+ * 0          slowdown limit                 stop limit                          backlog
+ *
+ * A stopped write carries on once the background threads are idle, even
+ * when they could not bring the store under the limit, as with tables
+ * the compaction strategy leaves in L0. The stop limit on immutable
+ * memtables is `Options::max_immutable_memtables`.
+ */
+#[derive(Clone, Debug)]
+pub struct WriteStallOptions {
+    /// Immutable memtables waiting for the flush thread that slow writes down.
+    pub immutable_memtables_slowdown: usize,
+    /// Tables in L0 that slow writes down.
+    pub level0_slowdown_trigger: usize,
+    /// Tables in L0 that stop writes.
+    pub level0_stop_trigger: usize,
+    /// Bytes the compaction strategy has yet to compact that slow writes
+    /// down, see `CompactionStrategy::pending_bytes`.
+    pub pending_compaction_bytes_slowdown: u64,
+    /// Bytes the compaction strategy has yet to compact that stop writes.
+    pub pending_compaction_bytes_stop: u64,
+    /// How long a slowed down write sleeps.
+    pub slowdown_delay: Duration,
+}
+
+impl Default for WriteStallOptions {
+    fn default() -> WriteStallOptions {
+        WriteStallOptions {
+            immutable_memtables_slowdown: 2,
+            level0_slowdown_trigger: 20,
+            level0_stop_trigger: 36,
+            pending_compaction_bytes_slowdown: 64 << 30,
+            pending_compaction_bytes_stop: 256 << 30,
+            slowdown_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+/// How often and how long writers were held back, see `KVStore::stall_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteStallStats {
+    /// Writes that slept past a slowdown limit.
+    pub slowdowns: u64,
+    pub slowdown_time: Duration,
+    /// Writes that waited past a stop limit.
+    pub stops: u64,
+    pub stop_time: Duration,
+}
+
+/// What the current backlog does to the next write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stall {
+    None,
+    Slowdown,
+    Stop,
+}
@@ -1,7 +1,26 @@
 #[cfg(test)]
 mod test {
+    use crate::sstable::discover::discover_sstables;
+    use crate::sstable::sst::{create_sstable, sstable_data_dir, SSTable};
+    use crate::sstable::value::Value;
     use crate::store::lsm_store::KVStore;
+    use crate::store::options::{
+        BlobOptions, CompactionFilter, Compression, Decision, DelimitedPrefix, Durability,
+        FifoCompaction, LeveledCompaction, Options, Priority, RateLimiter, SizeTieredCompaction,
+        TableOptions, WriteStallOptions,
+    };
+    use crate::utils::fault_fs::FaultInjectionFs;
+    use crate::utils::manual_clock::ManualClock;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::io::ErrorKind;
     use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
     use tempfile::tempdir;
 
     #[test]
@@ -13,11 +32,12 @@ mod test {
             let path = temp_dir.path().to_path_buf();
 
             let mut store = KVStore::new("test_add_item".to_owned(), 20, path.clone());
-            store.set(key, value);
-            match store.get(b"life") {
+            store.set(key, value).unwrap();
+            match store.get(b"life").unwrap() {
                 Some(v) => assert_eq!(v, value, "Expected value to be b'42'"),
                 None => panic!("Expected value to be b'42'"),
             }
+            drop(store);
             drop(path);
             temp_dir.close().unwrap();
         }));
@@ -42,18 +62,19 @@ mod test {
             let path = temp_dir.path().to_path_buf();
             let mut store = KVStore::new("test_sstable_read".to_owned(), 20, path.clone());
             for (key, value) in setup {
-                store.set(key, value);
+                store.set(key, value).unwrap();
             }
 
-            match store.get(b"key4") {
+            match store.get(b"key4").unwrap() {
                 Some(v) => assert_eq!(v, b"value400", "Value mismatch"),
                 None => panic!("Expected a value to be found'"),
             }
 
-            match store.get(b"key1") {
+            match store.get(b"key1").unwrap() {
                 Some(v) => assert_eq!(v, b"value121", "Value mismatch"),
                 None => panic!("Expected a value to be found'"),
             }
+            drop(store);
             drop(path);
             temp_dir.close().unwrap();
         }));
@@ -77,14 +98,15 @@ mod test {
             let path = temp_dir.path().to_path_buf();
             let mut store = KVStore::new("test_delete_key".to_owned(), 20, path.clone());
             for (key, value) in setup {
-                store.set(key, value);
+                store.set(key, value).unwrap();
             }
 
-            store.delete(b"key2");
+            store.delete(b"key2").unwrap();
 
-            if let Some(v) = store.get(b"key2") {
+            if let Some(v) = store.get(b"key2").unwrap() {
                 panic!("Unexpected value {:?} found", v);
             }
+            drop(store);
             drop(path);
             temp_dir.close().unwrap();
         }));
@@ -113,45 +135,1478 @@ mod test {
             let path = temp_dir.path().to_path_buf();
             let mut store = KVStore::new("test_compaction".to_owned(), 10, path);
             for (key, value) in setup {
-                store.set(key, value);
+                store.set(key, value).unwrap();
             }
 
-            match store.get(b"key1") {
+            match store.get(b"key1").unwrap() {
                 Some(v) => assert_eq!(v, b"value7", "Expected value to be b'value7'"),
                 None => panic!("Expected value7 to be found'"),
             }
-            match store.get(b"key2") {
+            match store.get(b"key2").unwrap() {
                 Some(v) => assert_eq!(v, b"value2", "Expected value to be b'value2'"),
                 None => panic!("Expected value2 to be found'"),
             }
-            match store.get(b"key3") {
+            match store.get(b"key3").unwrap() {
                 Some(v) => assert_eq!(v, b"value3", "Expected value to be b'value3'"),
                 None => panic!("Expected a value3 to be found'"),
             }
-            match store.get(b"key4") {
+            match store.get(b"key4").unwrap() {
                 Some(v) => assert_eq!(v, b"value4", "Expected value to be b'value3'"),
                 None => panic!("Expected a value4 to be found'"),
             }
-            match store.get(b"key5") {
+            match store.get(b"key5").unwrap() {
                 Some(v) => assert_eq!(v, b"value5", "Expected value to be b'value3'"),
                 None => panic!("Expected a value5 to be found'"),
             }
-            match store.get(b"key6") {
+            match store.get(b"key6").unwrap() {
                 Some(v) => assert_eq!(v, b"value6", "Expected value to be b'value3'"),
                 None => panic!("Expected a value6 to be found'"),
             }
-            match store.get(b"key7") {
+            match store.get(b"key7").unwrap() {
                 Some(v) => assert_eq!(v, b"value7", "Expected value to be b'value3'"),
                 None => panic!("Expected a value7 to be found'"),
             }
 
-            assert_eq!(
-                store.get_sstables_count(),
-                1,
-                "Compaction should result in 1 table."
+            store.wait_for_compaction().unwrap();
+            assert!(
+                store.get_sstables_count_at_level(0) < 4,
+                "Compaction should keep L0 below its trigger."
             );
+            assert_eq!(store.get_sstables_count_at_level(1), 1);
+            drop(store);
+            temp_dir.close().unwrap();
+        }));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_recover_unflushed_writes() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().to_path_buf();
+            let mut store = KVStore::new("test_recover".to_owned(), 1000, path.clone());
+            store.set(b"key1", b"value1").unwrap();
+            store.set(b"key2", b"value2").unwrap();
+            store.delete(b"key1").unwrap();
+            drop(store);
+
+            let mut store = KVStore::new("test_recover".to_owned(), 1000, path);
+            if let Some(v) = store.get(b"key1").unwrap() {
+                panic!("Unexpected value {:?} found", v);
+            }
+            match store.get(b"key2").unwrap() {
+                Some(v) => assert_eq!(v, b"value2", "Value mismatch"),
+                None => panic!("Expected value2 to be found'"),
+            }
+            drop(store);
+            temp_dir.close().unwrap();
+        }));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_reopen_finds_sstables() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().to_path_buf();
+            let mut store = KVStore::new("test_reopen".to_owned(), 1000, path.clone());
+            store.set(b"key1", b"value1").unwrap();
+            store.set(b"key2", b"value2").unwrap();
+            store.flush_memtable().unwrap();
+            drop(store);
+
+            let mut store = KVStore::new("test_reopen".to_owned(), 1000, path.clone());
+            assert_eq!(store.get_sstables_count(), 1);
+            store.set(b"key1", b"value3").unwrap();
+            store.flush_memtable().unwrap();
+            drop(store);
+
+            let mut store = KVStore::new("test_reopen".to_owned(), 1000, path);
+            match store.get(b"key1").unwrap() {
+                Some(v) => assert_eq!(v, b"value3", "Value mismatch"),
+                None => panic!("Expected value3 to be found'"),
+            }
+            match store.get(b"key2").unwrap() {
+                Some(v) => assert_eq!(v, b"value2", "Value mismatch"),
+                None => panic!("Expected value2 to be found'"),
+            }
+            drop(store);
             temp_dir.close().unwrap();
         }));
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_uncommitted_sstables_are_removed() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().to_path_buf();
+            let name = "test_uncommitted".to_owned();
+            let mut store = KVStore::new(name.clone(), 1000, path.clone());
+            store.set(b"key1", b"value1").unwrap();
+            store.flush_memtable().unwrap();
+            drop(store);
+
+            let mut map = BTreeMap::new();
+            map.insert(b"key1".to_vec(), Value::Put(b"half-written".to_vec()));
+            let mut sstable = create_sstable(1, 100, name.clone(), &path);
+            sstable.write(&map, &TableOptions::default()).unwrap();
+
+            let mut store = KVStore::new(name.clone(), 1000, path.clone());
+            assert_eq!(store.get_sstables_count(), 1);
+            match store.get(b"key1").unwrap() {
+                Some(v) => assert_eq!(v, b"value1", "Value mismatch"),
+                None => panic!("Expected value1 to be found'"),
+            }
+            let files = fs::read_dir(sstable_data_dir(&path, &name))
+                .unwrap()
+                .count();
+            assert_eq!(files, 1, "Only the committed sstable should remain");
+            drop(store);
+            temp_dir.close().unwrap();
+        }));
+        assert!(result.is_ok());
+    }
+
+    /// Path of the MANIFEST `CURRENT` names in the store `name`.
+    fn current_manifest(path: &std::path::Path, name: &str) -> std::path::PathBuf {
+        let store_dir = path.join(name).join("rkv");
+        let current = fs::read_to_string(store_dir.join("CURRENT")).unwrap();
+        store_dir.join(current.trim_end())
+    }
+
+    /// A store with a table per key, each flush committed in its own manifest edit.
+    fn store_with_flushes(path: &std::path::Path, name: &str, keys: &[&[u8]]) {
+        let mut store = KVStore::new(name.to_owned(), 1000, path.to_path_buf());
+        for key in keys {
+            store.set(key, b"value").unwrap();
+            store.flush_memtable().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_corrupt_manifest_keeps_tables() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_corrupt_manifest";
+        store_with_flushes(&path, name, &[b"key1", b"key2", b"key3"]);
+
+        // Flip a payload byte of the second record, the edit of the first flush.
+        let manifest = current_manifest(&path, name);
+        let mut bytes = fs::read(&manifest).unwrap();
+        let len = u64::from_le_bytes(bytes[4..12].try_into().unwrap()) as usize;
+        bytes[12 + len + 12] ^= 0xff;
+        fs::write(&manifest, bytes).unwrap();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            KVStore::new(name.to_owned(), 1000, path.clone());
+        }));
+        let message = panic_message(result.err().unwrap());
+        assert!(
+            message.contains("Bad checksum on the log record"),
+            "Unexpected panic: {}",
+            message
+        );
+        let tables = fs::read_dir(sstable_data_dir(&path, name)).unwrap().count();
+        assert_eq!(tables, 3, "No table may be removed after a failed recovery");
+    }
+
+    #[test]
+    fn test_torn_manifest_tail_keeps_tables() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_torn_manifest";
+        store_with_flushes(&path, name, &[b"key1", b"key2"]);
+
+        let manifest = current_manifest(&path, name);
+        let mut bytes = fs::read(&manifest).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&manifest, bytes).unwrap();
+
+        let mut store = KVStore::new(name.to_owned(), 1000, path.clone());
+        assert_eq!(store.get(b"key1").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(store.get_sstables_count(), 1);
+        let data_dir = sstable_data_dir(&path, name);
+        assert_eq!(fs::read_dir(&data_dir).unwrap().count(), 2);
+        // The next table is numbered past the one the torn edit named.
+        store.set(b"key3", b"value").unwrap();
+        store.flush_memtable().unwrap();
+        assert_eq!(fs::read_dir(&data_dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_open_refuses_store_without_manifest() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_no_manifest".to_owned();
+        // Tables written before the manifest were named after a uuid.
+        let data_dir = sstable_data_dir(&path, &name);
+        fs::create_dir_all(&data_dir).unwrap();
+        let table = data_dir.join("0-6f1e3a52-8c1d-4b57-9a0e-2d4c7b9e1f30.rkv");
+        fs::write(&table, b"table").unwrap();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            KVStore::new(name.clone(), 1000, path.clone());
+        }));
+        let message = panic_message(result.err().unwrap());
+        assert!(
+            message.contains("holds sstables but no manifest, older stores are not supported"),
+            "Unexpected panic: {}",
+            message
+        );
+        assert!(table.exists(), "The old table should be left alone");
+    }
+
+    fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+        match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().unwrap().to_string(),
+        }
+    }
+
+    fn collect_keys(store: &KVStore, range: impl std::ops::RangeBounds<Vec<u8>>) -> Vec<Vec<u8>> {
+        store
+            .scan(range)
+            .unwrap()
+            .map(|pair| pair.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_scan() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut store = KVStore::new("test_scan".to_owned(), 1000, path);
+        for i in 0..10 {
+            store.set(format!("key{}", i).as_bytes(), b"old").unwrap();
+        }
+        store.flush_memtable().unwrap();
+        store.set(b"key3", b"new").unwrap();
+        store.delete(b"key5").unwrap();
+        store.flush_memtable().unwrap();
+        store.set(b"key4", b"newer").unwrap();
+        store.delete(b"key6").unwrap();
+        store.set(b"key55", b"added").unwrap();
+
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = store.iter().unwrap().map(|p| p.unwrap()).collect();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = [
+            ("key0", "old"),
+            ("key1", "old"),
+            ("key2", "old"),
+            ("key3", "new"),
+            ("key4", "newer"),
+            ("key55", "added"),
+            ("key7", "old"),
+            ("key8", "old"),
+            ("key9", "old"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+        .collect();
+        assert_eq!(pairs, expected);
+
+        let keys = collect_keys(&store, b"key2".to_vec()..b"key7".to_vec());
+        assert_eq!(
+            keys,
+            vec![
+                b"key2".to_vec(),
+                b"key3".to_vec(),
+                b"key4".to_vec(),
+                b"key55".to_vec()
+            ]
+        );
+        let keys = collect_keys(
+            &store,
+            (
+                std::ops::Bound::Excluded(b"key7".to_vec()),
+                std::ops::Bound::Unbounded,
+            ),
+        );
+        assert_eq!(keys, vec![b"key8".to_vec(), b"key9".to_vec()]);
+        let keys = collect_keys(&store, ..=b"key1".to_vec());
+        assert_eq!(keys, vec![b"key0".to_vec(), b"key1".to_vec()]);
+        assert!(collect_keys(&store, b"key7".to_vec()..b"key2".to_vec()).is_empty());
+    }
+
+    #[test]
+    fn test_empty_value_is_not_a_delete() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let options = Options {
+            compaction: Arc::new(LeveledCompaction {
+                level0_file_num_trigger: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options("test_empty_value".to_owned(), 1000, path, options);
+        store.set(b"empty", b"").unwrap();
+        store.set(b"deleted", b"value").unwrap();
+        store.delete(b"deleted").unwrap();
+        assert_eq!(store.get(b"empty").unwrap(), Some(vec![]));
+        assert_eq!(store.get(b"deleted").unwrap(), None);
+
+        store.flush_memtable().unwrap();
+        store.set(b"other", b"value").unwrap();
+        store.flush_memtable().unwrap();
+        store.wait_for_compaction().unwrap();
+        assert_eq!(store.get_sstables_count(), 1);
+        assert_eq!(store.get(b"empty").unwrap(), Some(vec![]));
+        assert_eq!(store.get(b"deleted").unwrap(), None);
+
+        let keys: Vec<Vec<u8>> = store.iter().unwrap().map(|p| p.unwrap().0).collect();
+        assert_eq!(keys, vec![b"empty".to_vec(), b"other".to_vec()]);
+        let mut cursor = store.cursor().unwrap();
+        cursor.seek_to_first().unwrap();
+        assert_eq!(cursor.value(), Some(&b""[..]));
+    }
+
+    #[test]
+    fn test_cursor() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut store = KVStore::new("test_cursor".to_owned(), 1000, path);
+        for key in [&b"b"[..], b"d", b"f", b"h"] {
+            store.set(key, b"old").unwrap();
+        }
+        store.flush_memtable().unwrap();
+        store.set(b"d", b"new").unwrap();
+        store.delete(b"f").unwrap();
+        store.set(b"e", b"added").unwrap();
+
+        let mut cursor = store.cursor().unwrap();
+        assert!(!cursor.valid());
+        cursor.seek(b"c").unwrap();
+        assert_eq!(cursor.key(), Some(&b"d"[..]));
+        assert_eq!(cursor.value(), Some(&b"new"[..]));
+        cursor.next().unwrap();
+        assert_eq!(cursor.key(), Some(&b"e"[..]));
+        cursor.next().unwrap();
+        assert_eq!(cursor.key(), Some(&b"h"[..]));
+        cursor.prev().unwrap();
+        assert_eq!(cursor.key(), Some(&b"e"[..]));
+        cursor.prev().unwrap();
+        cursor.prev().unwrap();
+        assert_eq!(cursor.key(), Some(&b"b"[..]));
+        cursor.prev().unwrap();
+        assert!(!cursor.valid());
+
+        cursor.seek_for_prev(b"g").unwrap();
+        assert_eq!(cursor.key(), Some(&b"e"[..]));
+        cursor.seek_for_prev(b"a").unwrap();
+        assert!(!cursor.valid());
+        cursor.seek(b"i").unwrap();
+        assert!(!cursor.valid());
+        cursor.seek_to_first().unwrap();
+        assert_eq!(cursor.key(), Some(&b"b"[..]));
+        cursor.seek_to_last().unwrap();
+        assert_eq!(cursor.key(), Some(&b"h"[..]));
+    }
+
+    #[test]
+    fn test_cursor_matches_model() {
+        let temp_dir = tempdir().unwrap();
+        let options = Options {
+            table: TableOptions {
+                block_size: 32,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            "test_cursor_model".to_owned(),
+            200,
+            temp_dir.path().to_path_buf(),
+            options,
+        );
+        let mut model = BTreeMap::new();
+        let mut rng = StdRng::seed_from_u64(400);
+        for i in 0..400 {
+            let key = format!("key{:03}", rng.gen_range(0..100)).into_bytes();
+            if rng.gen_bool(0.2) {
+                store.delete(&key).unwrap();
+                model.remove(&key);
+            } else {
+                let value = format!("value{}", i).into_bytes();
+                store.set(&key, &value).unwrap();
+                model.insert(key, value);
+            }
+        }
+        let keys: Vec<Vec<u8>> = model.keys().cloned().collect();
+
+        let mut cursor = store.cursor().unwrap();
+        let mut position: Option<usize> = None;
+        for _ in 0..1000 {
+            match rng.gen_range(0..6) {
+                0 => {
+                    let target = format!("key{:03}", rng.gen_range(0..101)).into_bytes();
+                    cursor.seek(&target).unwrap();
+                    position = Some(keys.partition_point(|k| k < &target));
+                }
+                1 => {
+                    let target = format!("key{:03}", rng.gen_range(0..101)).into_bytes();
+                    cursor.seek_for_prev(&target).unwrap();
+                    position = keys.partition_point(|k| k <= &target).checked_sub(1);
+                }
+                2 => {
+                    cursor.seek_to_first().unwrap();
+                    position = Some(0);
+                }
+                3 => {
+                    cursor.seek_to_last().unwrap();
+                    position = keys.len().checked_sub(1);
+                }
+                4 => {
+                    cursor.next().unwrap();
+                    position = position.map(|p| p + 1);
+                }
+                _ => {
+                    cursor.prev().unwrap();
+                    position = position.and_then(|p| p.checked_sub(1));
+                }
+            }
+            position = position.filter(|p| *p < keys.len());
+            let expected = position.map(|p| (keys[p].as_slice(), model[&keys[p]].as_slice()));
+            let found = cursor.key().zip(cursor.value());
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_scan_prefix() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut store = KVStore::new("test_scan_prefix".to_owned(), 1000, path);
+        for key in [&b"a/1"[..], b"a/2", b"a0", b"b/1", b"\xff\xff"] {
+            store.set(key, b"value").unwrap();
+        }
+        store.flush_memtable().unwrap();
+        store.set(b"a/3", b"value").unwrap();
+        store.delete(b"a/1").unwrap();
+
+        let keys: Vec<Vec<u8>> = store
+            .scan_prefix(b"a/")
+            .unwrap()
+            .map(|pair| pair.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![b"a/2".to_vec(), b"a/3".to_vec()]);
+        assert_eq!(store.scan_prefix(b"a").unwrap().count(), 3);
+        assert_eq!(store.scan_prefix(b"\xff").unwrap().count(), 1);
+        assert_eq!(store.scan_prefix(b"").unwrap().count(), 5);
+        assert_eq!(store.scan_prefix(b"c/").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_prefix_filter_skips_sstables() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_prefix_filter".to_owned();
+        let options = Options {
+            table: TableOptions {
+                prefix_extractor: Some(Arc::new(DelimitedPrefix(b'/'))),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(name.clone(), 1000, path.clone(), options);
+        store.set(b"a/1", b"value").unwrap();
+        store.set(b"c/1", b"value").unwrap();
+        store.flush_memtable().unwrap();
+        // Break the data block but leave the filter intact.
+        for entry in fs::read_dir(sstable_data_dir(&path, &name)).unwrap() {
+            let entry_path = entry.unwrap().path();
+            let mut bytes = fs::read(&entry_path).unwrap();
+            bytes[0] ^= 0xff;
+            fs::write(&entry_path, bytes).unwrap();
+        }
+
+        assert_eq!(store.scan_prefix(b"b/").unwrap().count(), 0);
+        let first = |prefix: &[u8]| {
+            store
+                .scan_prefix(prefix)
+                .and_then(|mut pairs| pairs.next().unwrap())
+        };
+        assert!(first(b"c/").is_err());
+        // Not a whole prefix, so the filter cannot help.
+        assert!(first(b"b").is_err());
+    }
+
+    #[test]
+    fn test_scan_reports_corruption() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_scan_reports_corruption".to_owned();
+        let mut store = KVStore::new(name.clone(), 1000, path.clone());
+        store.set(b"key1", b"value1").unwrap();
+        store.flush_memtable().unwrap();
+        let data_dir = sstable_data_dir(&path, &name);
+        for entry in fs::read_dir(data_dir).unwrap() {
+            let entry_path = entry.unwrap().path();
+            let mut bytes = fs::read(&entry_path).unwrap();
+            bytes[0] ^= 0xff;
+            fs::write(&entry_path, bytes).unwrap();
+        }
+        store.set(b"key0", b"value0").unwrap();
+
+        let mut pairs = store.iter().unwrap();
+        let err = pairs.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(pairs.next().is_none());
+    }
+
+    #[test]
+    fn test_compression_change_keeps_old_tables() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_compression".to_owned();
+        let with_compression = |compression| Options {
+            table: TableOptions {
+                compression,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            name.clone(),
+            1000,
+            path.clone(),
+            with_compression(Compression::Zstd),
+        );
+        store.set(b"key1", b"value1").unwrap();
+        store.set(b"key2", b"value2").unwrap();
+        store.flush_memtable().unwrap();
+        drop(store);
+
+        let mut store =
+            KVStore::with_options(name, 1000, path, with_compression(Compression::Snappy));
+        store.set(b"key2", b"value3").unwrap();
+        store.flush_memtable().unwrap();
+        assert_eq!(store.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(store.get(b"key2").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_get_reports_corruption() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_get_reports_corruption".to_owned();
+        let mut store = KVStore::new(name.clone(), 1000, path.clone());
+        store.set(b"key1", b"value1").unwrap();
+        store.flush_memtable().unwrap();
+
+        let data_dir = sstable_data_dir(&path, &name);
+        for entry in fs::read_dir(data_dir).unwrap() {
+            let entry_path = entry.unwrap().path();
+            if entry_path.extension().unwrap() == "rkv" {
+                let mut bytes = fs::read(&entry_path).unwrap();
+                let last = bytes.len() - 1;
+                bytes[last] ^= 0xff;
+                fs::write(&entry_path, bytes).unwrap();
+            }
+        }
+
+        let err = store.get(b"key1").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    fn crash_after(durability: Durability, flushed: bool) -> (bool, bool) {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let fs = Arc::new(FaultInjectionFs::default());
+        let options = Options {
+            durability,
+            fs: fs.clone(),
+            ..Default::default()
+        };
+        let mut store =
+            KVStore::with_options("test_crash".to_owned(), 1000, path.clone(), options.clone());
+        store.set(b"key1", b"value1").unwrap();
+        if flushed {
+            store.flush_memtable().unwrap();
+        }
+        store.set(b"key2", b"value2").unwrap();
+        drop(store);
+
+        fs.crash(&path);
+        let mut store = KVStore::with_options("test_crash".to_owned(), 1000, path, options);
+        (
+            store.get(b"key1").unwrap().is_some(),
+            store.get(b"key2").unwrap().is_some(),
+        )
+    }
+
+    #[test]
+    fn test_crash_without_durability_loses_writes() {
+        assert_eq!(crash_after(Durability::None, true), (false, false));
+    }
+
+    #[test]
+    fn test_crash_keeps_flushed_writes() {
+        assert_eq!(crash_after(Durability::OnFlush, true), (true, false));
+        assert_eq!(crash_after(Durability::OnFlush, false), (false, false));
+    }
+
+    #[test]
+    fn test_crash_keeps_every_write() {
+        assert_eq!(crash_after(Durability::EveryWrite, true), (true, true));
+        assert_eq!(crash_after(Durability::EveryWrite, false), (true, true));
+    }
+
+    #[test]
+    fn test_failed_sync_fails_flush() {
+        let temp_dir = tempdir().unwrap();
+        let fs = Arc::new(FaultInjectionFs::default());
+        let options = Options {
+            durability: Durability::OnFlush,
+            fs: fs.clone(),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            "test_failed_sync".to_owned(),
+            1000,
+            temp_dir.path().to_path_buf(),
+            options,
+        );
+        store.set(b"key1", b"value1").unwrap();
+        fs.fail_syncs(true);
+        assert!(store.flush_memtable().is_err());
+    }
+
+    #[test]
+    fn test_large_keys_and_values() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = KVStore::new(
+            "test_large_keys".to_owned(),
+            1000,
+            temp_dir.path().to_path_buf(),
+        );
+        let key = vec![b'k'; 70_000];
+        let value = vec![b'v'; 100_000];
+        store.set(&key, &value).unwrap();
+        store.set(b"small", b"value").unwrap();
+        store.flush_memtable().unwrap();
+        assert_eq!(store.get(&key).unwrap(), Some(value));
+        assert_eq!(store.get(b"small").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let temp_dir = tempdir().unwrap();
+        let options = Options {
+            max_key_size: 8,
+            max_value_size: 16,
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            "test_size_limits".to_owned(),
+            1000,
+            temp_dir.path().to_path_buf(),
+            options,
+        );
+        store.set(&[b'k'; 8], &[b'v'; 16]).unwrap();
+        store.set(b"", b"").unwrap();
+        let err = store.set(&[b'k'; 9], b"value").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = store.set(b"key", &[b'v'; 17]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = store.delete(&[b'k'; 9]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(store.get(&[b'k'; 8]).unwrap(), Some(vec![b'v'; 16]));
+        assert_eq!(store.get(b"key").unwrap(), None);
+    }
+
+    fn blob_files(path: &std::path::Path, name: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(sstable_data_dir(path, name))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".blob"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn blob_options(gc_age_cutoff: f64) -> Options {
+        Options {
+            blob: BlobOptions {
+                min_blob_size: Some(100),
+                gc_age_cutoff,
+            },
+            compaction: Arc::new(LeveledCompaction {
+                level0_file_num_trigger: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_large_values_go_to_blob_files() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_blobs".to_owned();
+        let mut store =
+            KVStore::with_options(name.clone(), 100_000, path.clone(), blob_options(0.0));
+        store.set(b"big", &[7; 100]).unwrap();
+        store.set(b"small", &[1; 99]).unwrap();
+        store.set(b"deleted", &[2; 500]).unwrap();
+        store.delete(b"deleted").unwrap();
+        store.flush_memtable().unwrap();
+        assert_eq!(blob_files(&path, &name).len(), 1);
+        drop(store);
+
+        let mut store = KVStore::with_options(name, 100_000, path, blob_options(0.0));
+        assert_eq!(store.get(b"big").unwrap(), Some(vec![7; 100]));
+        assert_eq!(store.get(b"small").unwrap(), Some(vec![1; 99]));
+        assert_eq!(store.get(b"deleted").unwrap(), None);
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = store.iter().unwrap().map(|p| p.unwrap()).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (b"big".to_vec(), vec![7; 100]),
+                (b"small".to_vec(), vec![1; 99])
+            ]
+        );
+        let mut cursor = store.cursor().unwrap();
+        cursor.seek_to_first().unwrap();
+        assert_eq!(cursor.value(), Some(&[7; 100][..]));
+        cursor.next().unwrap();
+        assert_eq!(cursor.value(), Some(&[1; 99][..]));
+    }
+
+    #[test]
+    fn test_overwritten_blob_files_are_removed() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_blob_removal".to_owned();
+        let mut store =
+            KVStore::with_options(name.clone(), 100_000, path.clone(), blob_options(0.0));
+        for i in 0..10 {
+            store
+                .set(format!("key{}", i).as_bytes(), &[1; 200])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+        let first = blob_files(&path, &name);
+        for i in 0..10 {
+            store
+                .set(format!("key{}", i).as_bytes(), &[2; 200])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+        store.wait_for_compaction().unwrap();
+        assert_eq!(store.get_sstables_count(), 1);
+        let remaining = blob_files(&path, &name);
+        assert_eq!(remaining.len(), 1);
+        assert!(!remaining.contains(&first[0]));
+        drop(store);
+
+        let mut store =
+            KVStore::with_options(name.clone(), 100_000, path.clone(), blob_options(0.0));
+        assert_eq!(blob_files(&path, &name), remaining);
+        assert_eq!(store.get(b"key3").unwrap(), Some(vec![2; 200]));
+    }
+
+    #[test]
+    fn test_compaction_moves_old_blobs() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_blob_gc".to_owned();
+        let mut store =
+            KVStore::with_options(name.clone(), 100_000, path.clone(), blob_options(0.5));
+        for i in 0..10 {
+            store
+                .set(format!("key{}", i).as_bytes(), &[1; 200])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+        let first = blob_files(&path, &name);
+        for i in 0..5 {
+            store
+                .set(format!("key{}", i).as_bytes(), &[2; 200])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+        store.wait_for_compaction().unwrap();
+        // The live half of the first file was moved, so the file is gone.
+        let remaining = blob_files(&path, &name);
+        assert_eq!(remaining.len(), 2);
+        assert!(!remaining.contains(&first[0]));
+        drop(store);
+
+        let mut store = KVStore::with_options(name, 100_000, path, blob_options(0.5));
+        for i in 0..10 {
+            let expected = if i < 5 { vec![2; 200] } else { vec![1; 200] };
+            assert_eq!(
+                store.get(format!("key{}", i).as_bytes()).unwrap(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_uncommitted_blob_files_are_removed() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_uncommitted_blobs".to_owned();
+        let mut store =
+            KVStore::with_options(name.clone(), 100_000, path.clone(), blob_options(0.0));
+        store.set(b"key1", &[1; 200]).unwrap();
+        store.flush_memtable().unwrap();
+        drop(store);
+        let leftover = sstable_data_dir(&path, &name).join("000099.blob");
+        fs::write(&leftover, b"partial").unwrap();
+
+        let mut store = KVStore::with_options(name, 100_000, path, blob_options(0.0));
+        assert!(!leftover.exists());
+        assert_eq!(store.get(b"key1").unwrap(), Some(vec![1; 200]));
+    }
+
+    #[test]
+    fn test_leveled_compaction() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_leveled".to_owned();
+        let options = Options {
+            compaction: Arc::new(LeveledCompaction {
+                level0_file_num_trigger: 2,
+                max_bytes_for_level_base: 4096,
+                level_size_multiplier: 4,
+                target_file_size: 1024,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(name.clone(), 500, path.clone(), options);
+        let mut model = BTreeMap::new();
+        let mut rng = StdRng::seed_from_u64(3000);
+        for i in 0..3000 {
+            let key = format!("key{:05}", rng.gen_range(0..1000)).into_bytes();
+            if i % 7 == 0 {
+                store.delete(&key).unwrap();
+                model.remove(&key);
+            } else {
+                let value = format!("value{}", i).into_bytes();
+                store.set(&key, &value).unwrap();
+                model.insert(key, value);
+            }
+        }
+        store.wait_for_compaction().unwrap();
+        assert!(store.get_sstables_count_at_level(0) < 2);
+        assert!(store.get_sstables_count_at_level(1) > 1);
+        assert!(store.get_sstables_count_at_level(2) > 1);
+
+        let mut levels: BTreeMap<u16, Vec<_>> = BTreeMap::new();
+        for (_, sstable) in discover_sstables(&sstable_data_dir(&path, &name)) {
+            if let Some(range) = sstable.key_range().unwrap() {
+                levels.entry(sstable.get_level()).or_default().push(range);
+            }
+        }
+        for (level, ranges) in levels.iter_mut().filter(|(level, _)| **level > 0) {
+            ranges.sort();
+            for pair in ranges.windows(2) {
+                assert!(pair[0].1 < pair[1].0, "Tables of L{} overlap", level);
+            }
+        }
+
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = store.iter().unwrap().map(|p| p.unwrap()).collect();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = model.clone().into_iter().collect();
+        assert_eq!(pairs, expected);
+        for key in [
+            b"key00001".to_vec(),
+            b"key00500".to_vec(),
+            b"key00999".to_vec(),
+        ] {
+            assert_eq!(store.get(&key).unwrap(), model.get(&key).cloned());
+        }
+    }
+
+    #[test]
+    fn test_size_tiered_compaction() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_size_tiered".to_owned();
+        let options = || Options {
+            compaction: Arc::new(SizeTieredCompaction {
+                min_merge_width: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(name.clone(), 300, path.clone(), options());
+        let mut model = BTreeMap::new();
+        let mut rng = StdRng::seed_from_u64(500);
+        for i in 0..3000 {
+            let key = format!("key{:04}", rng.gen_range(0..500)).into_bytes();
+            if i % 9 == 0 {
+                store.delete(&key).unwrap();
+                model.remove(&key);
+            } else {
+                let value = vec![b'v'; rng.gen_range(1..200)];
+                store.set(&key, &value).unwrap();
+                model.insert(key, value);
+            }
+        }
+        store.wait_for_compaction().unwrap();
+        let mut sstables: Vec<SSTable> = discover_sstables(&sstable_data_dir(&path, &name))
+            .into_iter()
+            .map(|(_, sstable)| sstable)
+            .collect();
+        assert!(sstables.iter().all(|sstable| sstable.get_level() == 0));
+        assert_eq!(store.get_sstables_count_at_level(0), sstables.len());
+        // Compactions ran until no run of similar tables was left, in the order the store keeps.
+        sstables.sort_by_key(|sstable| sstable.flush_number().unwrap());
+        assert!(options().compaction.pick(&sstables).unwrap().is_none());
+
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = model.clone().into_iter().collect();
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = store.iter().unwrap().map(|p| p.unwrap()).collect();
+        assert_eq!(pairs, expected);
+        drop(store);
+
+        let mut store = KVStore::with_options(name, 300, path, options());
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = store.iter().unwrap().map(|p| p.unwrap()).collect();
+        assert_eq!(pairs, expected);
+        for key in model.keys().take(20) {
+            assert_eq!(store.get(key).unwrap(), model.get(key).cloned());
+        }
+    }
+
+    #[test]
+    fn test_readers_outlive_compaction() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_readers".to_owned();
+        let mut store =
+            KVStore::with_options(name.clone(), 100_000, path.clone(), blob_options(0.0));
+        for i in 0..10 {
+            store
+                .set(format!("key{}", i).as_bytes(), &[1; 200])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+        let first_table = discover_sstables(&sstable_data_dir(&path, &name))[0]
+            .1
+            .get_path()
+            .to_owned();
+        let first_blobs = blob_files(&path, &name);
+        let iter = store.iter().unwrap();
+
+        for i in 0..10 {
+            store
+                .set(format!("key{}", i).as_bytes(), &[2; 200])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+        store.wait_for_compaction().unwrap();
+        assert_eq!(store.get_sstables_count(), 1);
+        assert!(first_table.exists());
+
+        let values: Vec<_> = iter.map(|pair| pair.unwrap().1).collect();
+        assert_eq!(values, vec![vec![1; 200]; 10]);
+        assert!(!first_table.exists());
+        assert!(!blob_files(&path, &name).contains(&first_blobs[0]));
+        assert_eq!(store.get(b"key3").unwrap(), Some(vec![2; 200]));
+    }
+
+    #[test]
+    fn test_writes_stall_on_immutable_memtables() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let fs = Arc::new(FaultInjectionFs::default());
+        let options = Options {
+            fs: fs.clone(),
+            max_immutable_memtables: 1,
+            ..Default::default()
+        };
+        let name = "test_stall".to_owned();
+        let mut store = KVStore::with_options(name.clone(), 100, path.clone(), options.clone());
+
+        // Flushes fail, so the first full memtable stays immutable and the second stalls.
+        fs.fail_file_syncs(true);
+        let mut written = vec![];
+        for i in 0..100 {
+            let key = format!("key{:03}", i).into_bytes();
+            if store.set(&key, b"value").is_err() {
+                break;
+            }
+            written.push(key);
+        }
+        assert!(written.len() > 10 && written.len() < 100);
+        assert_eq!(store.get_sstables_count(), 0);
+        assert_eq!(store.stall_stats().stops, 1);
+        for key in &written {
+            assert_eq!(store.get(key).unwrap(), Some(b"value".to_vec()));
+        }
+        let scanned: Vec<_> = store.iter().unwrap().map(|p| p.unwrap().0).collect();
+        assert_eq!(scanned, written);
+        let mut cursor = store.cursor().unwrap();
+        cursor.seek_to_last().unwrap();
+        assert_eq!(cursor.key(), written.last().map(|key| key.as_slice()));
+
+        fs.fail_file_syncs(false);
+        store.set(b"key999", b"value").unwrap();
+        written.push(b"key999".to_vec());
+        store.wait_for_compaction().unwrap();
+        assert!(store.get_sstables_count() > 0);
+        drop(store);
+
+        let mut store = KVStore::with_options(name, 100, path, options);
+        for key in &written {
+            assert_eq!(store.get(key).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_reads_during_background_flushes() {
+        let temp_dir = tempdir().unwrap();
+        let options = Options {
+            compaction: Arc::new(LeveledCompaction {
+                level0_file_num_trigger: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            "test_background_reads".to_owned(),
+            200,
+            temp_dir.path().to_path_buf(),
+            options,
+        );
+        let written = Arc::new(AtomicUsize::new(0));
+        let readers: Vec<_> = (0..4)
+            .map(|reader| {
+                let mut store = store.clone();
+                let written = written.clone();
+                thread::spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(reader);
+                    while written.load(Ordering::SeqCst) < 2000 {
+                        let upto = written.load(Ordering::SeqCst);
+                        if upto == 0 {
+                            continue;
+                        }
+                        let i = rng.gen_range(0..upto);
+                        let key = format!("key{:04}", i).into_bytes();
+                        assert_eq!(store.get(&key).unwrap(), Some(key.clone()));
+                    }
+                })
+            })
+            .collect();
+        for i in 0..2000 {
+            let key = format!("key{:04}", i).into_bytes();
+            store.set(&key, &key).unwrap();
+            written.store(i + 1, Ordering::SeqCst);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        store.wait_for_compaction().unwrap();
+        assert_eq!(store.iter().unwrap().count(), 2000);
+    }
+
+    #[test]
+    fn test_writes_slow_down_and_stop() {
+        let temp_dir = tempdir().unwrap();
+        let options = Options {
+            // Nothing is ever merged, so L0 only grows.
+            compaction: Arc::new(SizeTieredCompaction {
+                min_merge_width: usize::MAX,
+                ..Default::default()
+            }),
+            stall: WriteStallOptions {
+                level0_slowdown_trigger: 2,
+                level0_stop_trigger: 4,
+                slowdown_delay: Duration::from_millis(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            "test_write_stalls".to_owned(),
+            100,
+            temp_dir.path().to_path_buf(),
+            options,
+        );
+        assert_eq!(store.stall_stats(), Default::default());
+        for i in 0..100 {
+            let key = format!("key{:03}", i).into_bytes();
+            store.set(&key, b"value").unwrap();
+        }
+        store.wait_for_compaction().unwrap();
+        assert!(store.get_sstables_count_at_level(0) >= 4);
+
+        let stats = store.stall_stats();
+        assert!(stats.slowdowns > 0);
+        assert!(stats.slowdown_time >= Duration::from_millis(2) * stats.slowdowns as u32);
+        assert!(stats.stops > 0);
+        for i in 0..100 {
+            let key = format!("key{:03}", i).into_bytes();
+            assert_eq!(store.get(&key).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    /// Keys of every entry in the store's sstables, deletes included.
+    fn table_keys(path: &std::path::Path, name: &str) -> Vec<(u16, Vec<u8>, Value)> {
+        let mut entries = vec![];
+        for (_, sstable) in discover_sstables(&sstable_data_dir(path, name)) {
+            for entry in sstable.iter().unwrap() {
+                let (key, value) = entry.unwrap();
+                entries.push((sstable.get_level(), key, value));
+            }
+        }
+        entries
+    }
+
+    fn compact_range_after_deletes(name: &str, options: Options) -> Vec<(u16, Vec<u8>, Value)> {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut store = KVStore::with_options(name.to_owned(), 200, path.clone(), options);
+        for i in 0..100 {
+            store
+                .set(format!("a/{:03}", i).as_bytes(), b"value")
+                .unwrap();
+            store
+                .set(format!("b/{:03}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        // Deletes are kept while the values they hide are in older tables.
+        store.compact_range(b"a/", b"c").unwrap();
+        for i in 0..100 {
+            store.delete(format!("a/{:03}", i).as_bytes()).unwrap();
+        }
+        store.set(b"a/new", b"value").unwrap();
+        store.wait_for_compaction().unwrap();
+        assert!(table_keys(&path, name)
+            .iter()
+            .any(|(_, _, value)| *value == Value::Delete));
+
+        store.compact_range(b"a/", b"a0").unwrap();
+        let keys: Vec<_> = store.iter().unwrap().map(|p| p.unwrap().0).collect();
+        assert_eq!(keys.len(), 101);
+        assert_eq!(keys[0], b"a/new".to_vec());
+        assert_eq!(store.get(b"b/050").unwrap(), Some(b"value".to_vec()));
+        drop(store);
+        table_keys(&path, name)
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let options = Options {
+            compaction: Arc::new(LeveledCompaction {
+                level0_file_num_trigger: 2,
+                num_levels: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let entries = compact_range_after_deletes("test_compact_range", options);
+        let tenant: Vec<_> = entries
+            .iter()
+            .filter(|(_, key, _)| key.starts_with(b"a/"))
+            .collect();
+        assert_eq!(tenant.len(), 1);
+        assert_eq!(tenant[0].0, 2);
+        assert!(entries.iter().all(|(_, _, value)| *value != Value::Delete));
+    }
+
+    #[test]
+    fn test_compact_range_size_tiered() {
+        let options = Options {
+            compaction: Arc::new(SizeTieredCompaction::default()),
+            ..Default::default()
+        };
+        let entries = compact_range_after_deletes("test_compact_range_tiered", options);
+        assert!(entries.iter().all(|(level, _, _)| *level == 0));
+        assert!(entries.iter().all(|(_, _, value)| *value != Value::Delete));
+        assert_eq!(
+            entries
+                .iter()
+                .filter(|(_, key, _)| key.starts_with(b"a/"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_deleted_keys_are_removed() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_tombstone_gc";
+        let options = Options {
+            compaction: Arc::new(LeveledCompaction {
+                level0_file_num_trigger: 2,
+                num_levels: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(name.to_owned(), 100_000, path.clone(), options);
+        for i in 0..100 {
+            store
+                .set(format!("key{:03}", i).as_bytes(), &[1; 100])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+        let data_dir = sstable_data_dir(&path, name);
+        let size = |dir: &std::path::Path| -> u64 {
+            fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().metadata().unwrap().len())
+                .sum()
+        };
+        let full = size(&data_dir);
+
+        for i in 0..100 {
+            store.delete(format!("key{:03}", i).as_bytes()).unwrap();
+        }
+        store.set(b"other", b"value").unwrap();
+        store.flush_memtable().unwrap();
+        store.wait_for_compaction().unwrap();
+        assert_eq!(store.get_sstables_count(), 1);
+        assert_eq!(store.get(b"key050").unwrap(), None);
+        drop(store);
+
+        let entries = table_keys(&path, name);
+        assert_eq!(
+            entries,
+            vec![(1, b"other".to_vec(), Value::Put(b"value".to_vec()))]
+        );
+        assert!(size(&data_dir) < full / 10);
+    }
+
+    struct ExpireSessions;
+
+    impl CompactionFilter for ExpireSessions {
+        fn filter(&self, _level: u16, key: &[u8], value: &[u8]) -> Decision {
+            if key.starts_with(b"session/") {
+                Decision::Remove
+            } else if key.starts_with(b"name/") {
+                Decision::ChangeValue(value.to_ascii_uppercase())
+            } else {
+                Decision::Keep
+            }
+        }
+    }
+
+    #[test]
+    fn test_compaction_filter() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_compaction_filter";
+        let options = Options {
+            compaction_filter: Some(Arc::new(ExpireSessions)),
+            blob: BlobOptions {
+                min_blob_size: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(name.to_owned(), 100_000, path.clone(), options);
+        let long_name = vec![b'x'; 200];
+        store.set(b"name/1", b"ada").unwrap();
+        store.set(b"name/2", &long_name).unwrap();
+        store.set(b"other", b"value").unwrap();
+        store.set(b"session/1", b"token").unwrap();
+        store.flush_memtable().unwrap();
+        // Nothing was compacted yet.
+        assert_eq!(store.get(b"session/1").unwrap(), Some(b"token".to_vec()));
+        assert_eq!(store.get(b"name/1").unwrap(), Some(b"ada".to_vec()));
+
+        store.compact_range(b"a", b"z").unwrap();
+        assert_eq!(store.get(b"session/1").unwrap(), None);
+        assert_eq!(store.get(b"name/1").unwrap(), Some(b"ADA".to_vec()));
+        assert_eq!(
+            store.get(b"name/2").unwrap(),
+            Some(long_name.to_ascii_uppercase())
+        );
+        assert_eq!(store.get(b"other").unwrap(), Some(b"value".to_vec()));
+        drop(store);
+
+        let keys: Vec<Vec<u8>> = table_keys(&path, name)
+            .into_iter()
+            .map(|(_, key, _)| key)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![b"name/1".to_vec(), b"name/2".to_vec(), b"other".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_set_with_ttl() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_set_with_ttl";
+        let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+        let options = || Options {
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(name.to_owned(), 100_000, path.clone(), options());
+        store.set(b"session/1", b"old").unwrap();
+        store.flush_memtable().unwrap();
+        store
+            .set_with_ttl(b"session/1", b"token", Duration::from_millis(100))
+            .unwrap();
+        store
+            .set_with_ttl(b"session/2", b"token", Duration::from_secs(3600))
+            .unwrap();
+        store.set(b"user/1", b"ada").unwrap();
+        assert_eq!(store.get(b"session/1").unwrap(), Some(b"token".to_vec()));
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(store.get(b"session/1").unwrap(), Some(b"token".to_vec()));
+        drop(store);
+
+        // The deadlines survive a restart, through the write-ahead log and a flush.
+        let mut store = KVStore::with_options(name.to_owned(), 100_000, path.clone(), options());
+        store.flush_memtable().unwrap();
+        assert_eq!(store.get(b"session/1").unwrap(), Some(b"token".to_vec()));
+        clock.advance(Duration::from_millis(1));
+        // The expired value hides the one it replaced.
+        assert_eq!(store.get(b"session/1").unwrap(), None);
+        assert_eq!(store.get(b"session/2").unwrap(), Some(b"token".to_vec()));
+        let keys: Vec<Vec<u8>> = store.iter().unwrap().map(|p| p.unwrap().0).collect();
+        assert_eq!(keys, vec![b"session/2".to_vec(), b"user/1".to_vec()]);
+        let mut cursor = store.cursor().unwrap();
+        cursor.seek_to_first().unwrap();
+        assert_eq!(cursor.key(), Some(&b"session/2"[..]));
+
+        store.compact_range(b"a", b"z").unwrap();
+        drop(cursor);
+        drop(store);
+        let keys: Vec<Vec<u8>> = table_keys(&path, name)
+            .into_iter()
+            .map(|(_, key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![b"session/2".to_vec(), b"user/1".to_vec()]);
+    }
+
+    #[test]
+    fn test_fifo_compaction() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let name = "test_fifo_compaction";
+        let options = Options {
+            compaction: Arc::new(FifoCompaction {
+                max_table_files_size: 20_000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(name.to_owned(), 100_000, path.clone(), options);
+        for batch in 0..10 {
+            for i in 0..20 {
+                let key = format!("metric{:02}/{:02}", batch, i).into_bytes();
+                store.set(&key, &[b'v'; 200]).unwrap();
+            }
+            store.flush_memtable().unwrap();
+        }
+        store.wait_for_compaction().unwrap();
+
+        let sstables = discover_sstables(&sstable_data_dir(&path, name));
+        let size: u64 = sstables
+            .iter()
+            .map(|(_, sstable)| sstable.file_size().unwrap())
+            .sum();
+        assert!(size <= 20_000);
+        assert!(sstables.len() < 10);
+        assert_eq!(store.get_sstables_count_at_level(0), sstables.len());
+        // Whole flushes are gone, the most recent ones are untouched.
+        let oldest = 10 - sstables.len();
+        assert_eq!(store.get(b"metric00/00").unwrap(), None);
+        assert_eq!(
+            store
+                .get(format!("metric{:02}/00", oldest).as_bytes())
+                .unwrap(),
+            Some(vec![b'v'; 200])
+        );
+        assert_eq!(
+            store
+                .get(format!("metric{:02}/19", oldest - 1).as_bytes())
+                .unwrap(),
+            None
+        );
+        assert_eq!(store.iter().unwrap().count(), sstables.len() * 20);
+        for (_, sstable) in &sstables {
+            assert_eq!(sstable.iter().unwrap().count(), 20);
+        }
+    }
+
+    #[test]
+    fn test_rate_limited_background_writes() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let limiter = Arc::new(RateLimiter::new(200_000));
+        let options = Options {
+            compaction: Arc::new(LeveledCompaction {
+                level0_file_num_trigger: 2,
+                ..Default::default()
+            }),
+            rate_limiter: Some(limiter.clone()),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            "test_rate_limiter".to_owned(),
+            100_000,
+            path.clone(),
+            options,
+        );
+        for round in 0..2 {
+            for i in 0..100 {
+                let value = vec![round as u8; 500];
+                store
+                    .set(format!("key{:03}", i).as_bytes(), &value)
+                    .unwrap();
+            }
+            store.flush_memtable().unwrap();
+        }
+        store.wait_for_compaction().unwrap();
+        assert_eq!(store.get_sstables_count_at_level(1), 1);
+        let flushed = limiter.total_bytes(Priority::High);
+        let compacted = limiter.total_bytes(Priority::Low);
+        assert!(flushed > 2 * 100 * 500);
+        assert!(compacted > 100 * 500 && compacted < flushed);
+
+        // The running store picks up a new rate, even in a flush already held back by the old
+        // one: at 1KB/s this flush would take close to a minute.
+        limiter.set_bytes_per_second(1_000);
+        for i in 0..100 {
+            store
+                .set(format!("key{:03}", i).as_bytes(), &[2; 500])
+                .unwrap();
+        }
+        let started = Instant::now();
+        let mut flusher = store.clone();
+        let flush = thread::spawn(move || flusher.flush_memtable());
+        thread::sleep(Duration::from_millis(200));
+        assert!(!flush.is_finished());
+        limiter.set_bytes_per_second(0);
+        flush.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(20));
+        assert_eq!(store.get(b"key042").unwrap(), Some(vec![2; 500]));
+        drop(store);
+    }
+
+    #[test]
+    fn test_fifo_compaction_does_not_stall() {
+        let temp_dir = tempdir().unwrap();
+        let options = Options {
+            compaction: Arc::new(FifoCompaction::default()),
+            ..Default::default()
+        };
+        let mut store = KVStore::with_options(
+            "test_fifo_stall".to_owned(),
+            100_000,
+            temp_dir.path().to_path_buf(),
+            options,
+        );
+        let flushes = WriteStallOptions::default().level0_stop_trigger + 4;
+        for i in 0..flushes {
+            store
+                .set(format!("metric{:03}", i).as_bytes(), b"value")
+                .unwrap();
+            store.flush_memtable().unwrap();
+        }
+        store.wait_for_compaction().unwrap();
+        assert_eq!(store.get_sstables_count_at_level(0), flushes);
+        assert_eq!(store.stall_stats(), Default::default());
+    }
 }
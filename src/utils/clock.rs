@@ -0,0 +1,17 @@
+use crate::sstable::value::now_millis;
+
+/// Where the store reads the time expiring values are checked against.
+///
+/// Tests swap it out to move past expiry times without waiting for them.
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the Unix epoch.
+    fn now_millis(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        now_millis()
+    }
+}
@@ -0,0 +1,110 @@
+use crate::utils::fs::{FileSystem, OsFileSystem};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct State {
+    /// Length of each file at its last sync.
+    synced: HashMap<PathBuf, u64>,
+    /// Entries of each directory at its last sync.
+    dir_entries: HashMap<PathBuf, HashSet<PathBuf>>,
+    fail_syncs: bool,
+    fail_file_syncs: bool,
+}
+
+/// A `FileSystem` that remembers what has been synced, and can throw the
+/// rest away the way a power loss would.
+#[derive(Default)]
+pub struct FaultInjectionFs {
+    state: Mutex<State>,
+}
+
+impl FaultInjectionFs {
+    /// Make every following sync fail.
+    pub fn fail_syncs(&self, fail: bool) {
+        self.state.lock().unwrap().fail_syncs = fail;
+    }
+
+    /// Make every following file sync fail, directories still sync.
+    pub fn fail_file_syncs(&self, fail: bool) {
+        self.state.lock().unwrap().fail_file_syncs = fail;
+    }
+
+    /// Drop everything under `root` that was not synced.
+    ///
+    /// Files missing from their directory's last sync disappear, the others
+    /// are cut back to their length at their last sync.
+    pub fn crash(&self, root: &Path) {
+        let state = self.state.lock().unwrap();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let listed = state
+                    .dir_entries
+                    .get(&dir)
+                    .is_some_and(|entries| entries.contains(&path));
+                if !listed {
+                    fs::remove_file(&path).unwrap();
+                    continue;
+                }
+                let len = state.synced.get(&path).copied().unwrap_or(0);
+                let file = OpenOptions::new().write(true).open(&path).unwrap();
+                file.set_len(len).unwrap();
+            }
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.state.lock().unwrap().fail_syncs {
+            return Err(Error::other("injected sync failure"));
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for FaultInjectionFs {
+    fn sync_file(&self, path: &Path) -> Result<()> {
+        self.check()?;
+        if self.state.lock().unwrap().fail_file_syncs {
+            return Err(Error::other("injected file sync failure"));
+        }
+        let len = fs::metadata(path)?.len();
+        self.state
+            .lock()
+            .unwrap()
+            .synced
+            .insert(path.to_path_buf(), len);
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        self.check()?;
+        let entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<HashSet<PathBuf>>>()?;
+        self.state
+            .lock()
+            .unwrap()
+            .dir_entries
+            .insert(path.to_path_buf(), entries);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        OsFileSystem.rename(from, to)?;
+        let mut state = self.state.lock().unwrap();
+        match state.synced.remove(from) {
+            Some(len) => state.synced.insert(to.to_path_buf(), len),
+            None => state.synced.remove(to),
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs::{self, File};
+use std::io::Result;
+use std::path::Path;
+use std::sync::Arc;
+
+/// How hard the store works to keep acknowledged writes across a power loss.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    /// Leave it to the operating system to write data out.
+    None,
+    /// Sync sstables, the manifest and their directories whenever the memtable is flushed or
+    /// tables are compacted. Writes since the last flush can be lost.
+    OnFlush,
+    /// Like `OnFlush`, and also sync the write-ahead log before a write is acknowledged.
+    EveryWrite,
+}
+
+/// The file system calls that make data durable.
+///
+/// The store goes through this trait for every sync and rename so that
+/// tests can simulate what a crash would leave behind.
+pub trait FileSystem: Send + Sync {
+    /// Persist the contents of the file at `path`.
+    fn sync_file(&self, path: &Path) -> Result<()>;
+    /// Persist the entries of the directory at `path`, files created or removed in it.
+    fn sync_dir(&self, path: &Path) -> Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+}
+
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn sync_file(&self, path: &Path) -> Result<()> {
+        File::open(path)?.sync_data()
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        File::open(path)?.sync_all()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to)
+    }
+}
+
+/// Applies a `Durability` policy through a `FileSystem`.
+#[derive(Clone)]
+pub struct SyncPolicy {
+    durability: Durability,
+    fs: Arc<dyn FileSystem>,
+}
+
+impl SyncPolicy {
+    pub fn new(durability: Durability, fs: Arc<dyn FileSystem>) -> SyncPolicy {
+        SyncPolicy { durability, fs }
+    }
+
+    /// Called after every write-ahead log append.
+    pub fn on_write(&self, path: &Path) -> Result<()> {
+        match self.durability {
+            Durability::EveryWrite => self.fs.sync_file(path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Called once a file written by a flush or compaction, or the manifest, is complete.
+    pub fn on_flush(&self, path: &Path) -> Result<()> {
+        match self.durability {
+            Durability::None => Ok(()),
+            _ => self.fs.sync_file(path),
+        }
+    }
+
+    /// Called after files were created or removed in `dir`.
+    pub fn on_dir_change(&self, dir: &Path) -> Result<()> {
+        match self.durability {
+            Durability::None => Ok(()),
+            _ => self.fs.sync_dir(dir),
+        }
+    }
+
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.fs.rename(from, to)
+    }
+}
+
+impl Default for SyncPolicy {
+    fn default() -> SyncPolicy {
+        SyncPolicy::new(Durability::OnFlush, Arc::new(OsFileSystem))
+    }
+}
@@ -1,42 +1,72 @@
-use crate::sstable::constants::WORD;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::fs::File;
-use std::io::{Read, Result, Seek, SeekFrom, Write};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+/// Longest encoding of a `u64` varint.
+const MAX_VARINT_LEN: usize = 10;
+
+/**
+ * Write `n` as a varint: 7 bits per byte, least significant group first,
+ * the high bit set on every byte but the last.
+ *
+ * Lengths below 128 take a single byte, and no length is too large.
+ */
+pub fn write_varint<W: Write>(writer: &mut W, mut n: u64) -> Result<()> {
+    while n >= 0x80 {
+        writer.write_u8((n as u8) | 0x80)?;
+        n >>= 7;
+    }
+    writer.write_u8(n as u8)
+}
+
+pub fn read_varint<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut n: u64 = 0;
+    for i in 0..MAX_VARINT_LEN {
+        let byte = reader.read_u8()?;
+        let bits = (byte & 0x7f) as u64;
+        if i == MAX_VARINT_LEN - 1 && bits > 1 {
+            break;
+        }
+        n |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(corruption("varint overflows 64 bits".to_owned()))
+}
 
 pub fn set_key(buf: &mut Vec<u8>, key_len: usize, key: &[u8]) -> Result<()> {
-    buf.write_u16::<LittleEndian>(key_len as u16)?;
+    write_varint(buf, key_len as u64)?;
     buf.write_all(key)
 }
 
 pub fn set_value(buf: &mut Vec<u8>, value_len: usize, value: &[u8]) -> Result<()> {
-    buf.write_u32::<LittleEndian>(value_len as u32)?;
+    write_varint(buf, value_len as u64)?;
     buf.write_all(value)
 }
 
-pub fn key_value_at(pos: u64, index: &mut File, data: &mut File) -> Result<(Vec<u8>, Vec<u8>)> {
-    index.seek(SeekFrom::Start(pos * WORD as u64))?;
-    let data_mid = index.read_u64::<LittleEndian>()?;
-    data.seek(SeekFrom::Start(data_mid))?;
-    let key_len = data.read_u16::<LittleEndian>()?;
-    let mut key_buf = vec![0; key_len as usize];
-    data.read_exact(key_buf.as_mut_slice())?;
-
-    let value_len = data.read_u32::<LittleEndian>()?;
-    let mut value_buf = vec![0; value_len as usize];
-    data.read_exact(value_buf.as_mut_slice())?;
-    Ok((key_buf, value_buf))
+/// Read exactly `len` bytes without trusting `len` enough to allocate it upfront.
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("expected {} bytes, found {}", len, buf.len()),
+        ));
+    }
+    Ok(buf)
 }
 
-pub fn set_index(index_file: &mut File, index: u64) -> Result<()> {
-    index_file.write_u64::<LittleEndian>(index)
+pub fn get_key<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let key_len = read_varint(reader)?;
+    read_bytes(reader, key_len)
 }
 
-pub fn get_index_range(index: &mut File) -> (u64, u64) {
-    let first_address = index.seek(SeekFrom::Start(0));
-    let last_address = index.seek(SeekFrom::End(0));
+pub fn get_value<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let value_len = read_varint(reader)?;
+    read_bytes(reader, value_len)
+}
 
-    match (first_address, last_address) {
-        (Ok(first), Ok(last)) => (first / WORD as u64, last / WORD as u64),
-        e => panic!("Failed to get index bounds. Reason: {:?}", e),
-    }
+pub fn corruption(reason: String) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
 }
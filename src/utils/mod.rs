@@ -1 +1,11 @@
+pub mod clock;
+#[cfg(test)]
+pub mod fault_fs;
+pub mod fs;
 pub mod futil;
+#[cfg(test)]
+pub mod manual_clock;
+pub mod rate_limiter;
+#[cfg(test)]
+mod utils_test;
+pub mod worker;
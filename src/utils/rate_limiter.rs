@@ -0,0 +1,155 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How much of a second's worth of bytes the bucket holds, the longest burst it lets through.
+const REFILL_PERIOD: Duration = Duration::from_millis(100);
+
+/// Which background writes a `RateLimiter` lets through first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Flushes: writers stall when they fall behind.
+    High,
+    /// Compactions.
+    Low,
+}
+
+struct Bucket {
+    bytes_per_second: u64,
+    available: f64,
+    refilled_at: Instant,
+    /// `High` requests waiting for bytes, `Low` ones wait until there are none.
+    high_waiting: usize,
+    /// Bytes granted so far, `High` then `Low`.
+    granted: [u64; 2],
+}
+
+impl Bucket {
+    fn capacity(&self) -> f64 {
+        (self.bytes_per_second as f64 * REFILL_PERIOD.as_secs_f64()).max(1.0)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.available =
+            (self.available + elapsed * self.bytes_per_second as f64).min(self.capacity());
+        self.refilled_at = now;
+    }
+}
+
+/**
+ * A token bucket over the bytes flushes and compactions write, so that
+ * background work leaves disk bandwidth to foreground reads.
+ *
+ * The bucket fills at `bytes_per_second` and holds up to a tenth of a
+ * second's worth. A write takes bytes out before it goes to disk, and
+ * waits while the bucket is empty:
+ *
+ * bucket: |####      |  <- refills at bytes_per_second
+ * flush:  take 64K    <- High, served first
+ * compaction: waits   <- Low, until no High request is waiting
+ *
+ * Flushes are still limited, but never wait behind compactions. Share one
+ * limiter between stores to bound their writes together, and keep a clone
+ * to change the rate while they run.
+ *
+ * # Example
+ * ```
+ * use rkv::store::options::{Options, RateLimiter};
+ * use std::sync::Arc;
+ *
+ * let limiter = Arc::new(RateLimiter::new(16 << 20));
+ * let options = Options {
+ *     rate_limiter: Some(limiter.clone()),
+ *     ..Default::default()
+ * };
+ * // Later, at night:
+ * limiter.set_bytes_per_second(64 << 20);
+ * ```
+ */
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+    changed: Condvar,
+}
+
+impl RateLimiter {
+    /// A limiter letting `bytes_per_second` through, `0` for no limit.
+    pub fn new(bytes_per_second: u64) -> RateLimiter {
+        let mut bucket = Bucket {
+            bytes_per_second,
+            available: 0.0,
+            refilled_at: Instant::now(),
+            high_waiting: 0,
+            granted: [0; 2],
+        };
+        bucket.available = bucket.capacity();
+        RateLimiter {
+            bucket: Mutex::new(bucket),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Bucket> {
+        match self.bucket.lock() {
+            Ok(bucket) => bucket,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.lock().bytes_per_second
+    }
+
+    /// Change the rate, `0` for no limit. Writes waiting for bytes pick it up right away.
+    pub fn set_bytes_per_second(&self, bytes_per_second: u64) {
+        let mut bucket = self.lock();
+        bucket.refill();
+        bucket.bytes_per_second = bytes_per_second;
+        bucket.available = bucket.available.min(bucket.capacity());
+        self.changed.notify_all();
+    }
+
+    /// Bytes let through so far at `priority`.
+    pub fn total_bytes(&self, priority: Priority) -> u64 {
+        self.lock().granted[priority as usize]
+    }
+
+    /// Block until `bytes` may be written at `priority`.
+    ///
+    /// Requests larger than the bucket are let through a bucket at a time.
+    pub fn request(&self, bytes: u64, priority: Priority) {
+        let mut bucket = self.lock();
+        let mut remaining = bytes;
+        let mut waiting = false;
+        while remaining > 0 {
+            bucket.refill();
+            if bucket.bytes_per_second == 0 {
+                break;
+            }
+            let yields = priority == Priority::Low && bucket.high_waiting > 0;
+            let wanted = (remaining as f64).min(bucket.capacity()).max(1.0);
+            if !yields && bucket.available >= wanted {
+                let granted = remaining.min(bucket.available as u64);
+                bucket.available -= granted as f64;
+                remaining -= granted;
+                continue;
+            }
+            if priority == Priority::High && !waiting {
+                bucket.high_waiting += 1;
+                waiting = true;
+            }
+            let shortfall = (wanted - bucket.available).max(0.0);
+            let wait = Duration::from_secs_f64(shortfall / bucket.bytes_per_second as f64)
+                .clamp(Duration::from_millis(1), REFILL_PERIOD);
+            bucket = match self.changed.wait_timeout(bucket, wait) {
+                Ok((bucket, _)) => bucket,
+                Err(e) => panic!("Failed to lock. Reason: {}", e),
+            };
+        }
+        bucket.granted[priority as usize] += bytes;
+        if waiting {
+            bucket.high_waiting -= 1;
+            self.changed.notify_all();
+        }
+    }
+}
@@ -0,0 +1,146 @@
+#[cfg(test)]
+mod test {
+    use crate::utils::futil::{get_key, read_varint, set_key, write_varint};
+    use crate::utils::rate_limiter::{Priority, RateLimiter};
+    use crate::utils::worker::Worker;
+    use std::io::{Cursor, Error, ErrorKind};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_varint_round_trip() {
+        for n in [0, 127, 128, 16383, 16384, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            write_varint(&mut buf, n).unwrap();
+            assert_eq!(read_varint(&mut Cursor::new(&buf)).unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn test_varint_lengths() {
+        for (n, len) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16383, 2),
+            (16384, 3),
+            (u64::MAX, 10),
+        ] {
+            let mut buf = vec![];
+            write_varint(&mut buf, n).unwrap();
+            assert_eq!(buf.len(), len);
+        }
+    }
+
+    #[test]
+    fn test_bad_varints() {
+        let truncated = read_varint(&mut Cursor::new(&[0x80, 0x80])).unwrap_err();
+        assert_eq!(truncated.kind(), ErrorKind::UnexpectedEof);
+        let mut overflowing = vec![0xff; 9];
+        overflowing.push(0x02);
+        let overflow = read_varint(&mut Cursor::new(&overflowing)).unwrap_err();
+        assert_eq!(overflow.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_key_round_trip() {
+        for len in [0, 1, 127, 128, 65535, 65536, 70_000] {
+            let key = vec![b'k'; len];
+            let mut buf = vec![];
+            set_key(&mut buf, key.len(), &key).unwrap();
+            assert_eq!(get_key(&mut Cursor::new(&buf)).unwrap(), key);
+        }
+        let mut buf = vec![];
+        set_key(&mut buf, 4, b"key1").unwrap();
+        buf.pop();
+        let err = get_key(&mut Cursor::new(&buf)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_worker_runs_scheduled_work() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = runs.clone();
+        let worker = Worker::spawn("test-worker".to_owned(), move || {
+            match counted.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(Error::other("first run fails")),
+                _ => Ok(()),
+            }
+        })
+        .unwrap();
+        worker.wait().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        worker.schedule();
+        assert_eq!(worker.wait().unwrap_err().to_string(), "first run fails");
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        // The error is only reported once.
+        worker.wait().unwrap();
+
+        for _ in 0..10 {
+            worker.schedule();
+        }
+        worker.wait().unwrap();
+        let done = runs.load(Ordering::SeqCst);
+        assert!((2..=11).contains(&done));
+        drop(worker);
+        assert_eq!(runs.load(Ordering::SeqCst), done);
+    }
+
+    #[test]
+    fn test_worker_survives_panics() {
+        let worker = Worker::spawn("test-worker".to_owned(), || panic!("boom")).unwrap();
+        worker.schedule();
+        assert!(worker.wait().is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter_paces_requests() {
+        // Starts with a tenth of a second worth of bytes.
+        let limiter = RateLimiter::new(100_000);
+        let started = Instant::now();
+        limiter.request(10_000, Priority::Low);
+        limiter.request(30_000, Priority::Low);
+        assert!(started.elapsed() >= Duration::from_millis(290));
+        assert_eq!(limiter.total_bytes(Priority::Low), 40_000);
+        assert_eq!(limiter.total_bytes(Priority::High), 0);
+    }
+
+    #[test]
+    fn test_rate_limiter_serves_high_priority_first() {
+        let limiter = Arc::new(RateLimiter::new(100_000));
+        limiter.request(10_000, Priority::Low);
+        let low = {
+            let limiter = limiter.clone();
+            thread::spawn(move || {
+                limiter.request(60_000, Priority::Low);
+                Instant::now()
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        limiter.request(30_000, Priority::High);
+        let high_done = Instant::now();
+        assert!(high_done < low.join().unwrap());
+    }
+
+    #[test]
+    fn test_rate_limiter_changes_rate() {
+        let limiter = Arc::new(RateLimiter::new(1_000));
+        let waiting = {
+            let limiter = limiter.clone();
+            thread::spawn(move || limiter.request(1 << 20, Priority::Low))
+        };
+        thread::sleep(Duration::from_millis(50));
+        let started = Instant::now();
+        limiter.set_bytes_per_second(0);
+        waiting.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(limiter.bytes_per_second(), 0);
+
+        limiter.set_bytes_per_second(1 << 30);
+        limiter.request(1 << 20, Priority::High);
+        assert_eq!(limiter.total_bytes(Priority::High), 1 << 20);
+    }
+}
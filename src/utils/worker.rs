@@ -0,0 +1,123 @@
+use log::error;
+use std::io::{Error, Result};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+#[derive(Default)]
+struct State {
+    /// A flush happened since the work last started.
+    pending: bool,
+    running: bool,
+    shutdown: bool,
+    /// Failure of the last run, kept until someone waits for it.
+    error: Option<Error>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(e) => panic!("Failed to lock. Reason: {}", e),
+        }
+    }
+}
+
+/**
+ * A thread that runs flushes or compactions off the write path.
+ *
+ * Writers call `schedule` and return, the thread then runs the work it
+ * was given, which carries on until nothing is left to do:
+ *
+ * writer:  |set|set|freeze|set|set|set|freeze|set|
+ * worker:              |---flush---|      |---flush---|
+ *
+ * Requests made while a run is in progress are folded into one more run.
+ * Dropping the worker lets the current run finish and stops the thread.
+ */
+pub struct Worker {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Start a thread named `name` that calls `work` whenever it is scheduled.
+    pub fn spawn<F>(name: String, mut work: F) -> Result<Worker>
+    where
+        F: FnMut() -> Result<()> + Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        let worker = shared.clone();
+        let thread = thread::Builder::new().name(name).spawn(move || loop {
+            let mut state = worker.lock();
+            while !state.pending && !state.shutdown {
+                state = match worker.changed.wait(state) {
+                    Ok(state) => state,
+                    Err(e) => panic!("Failed to lock. Reason: {}", e),
+                };
+            }
+            if state.shutdown {
+                return;
+            }
+            state.pending = false;
+            state.running = true;
+            drop(state);
+
+            // A panic must not leave `running` set, that would block `wait` forever.
+            let outcome = panic::catch_unwind(AssertUnwindSafe(&mut work))
+                .unwrap_or_else(|_| Err(Error::other("the background work panicked")));
+            let mut state = worker.lock();
+            state.running = false;
+            if let Err(e) = outcome {
+                error!("Background work failed because {}", e);
+                state.error = Some(e);
+            }
+            worker.changed.notify_all();
+        })?;
+        Ok(Worker {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Ask for another run, without waiting for it.
+    pub fn schedule(&self) {
+        self.shared.lock().pending = true;
+        self.shared.changed.notify_all();
+    }
+
+    /// Block until no run is due or in progress.
+    ///
+    /// Returns the error of the last failed run, if no earlier call returned it.
+    pub fn wait(&self) -> Result<()> {
+        let mut state = self.shared.lock();
+        while state.pending || state.running {
+            state = match self.shared.changed.wait(state) {
+                Ok(state) => state,
+                Err(e) => panic!("Failed to lock. Reason: {}", e),
+            };
+        }
+        match state.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The background thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::utils::fs::SyncPolicy;
+use crate::utils::futil;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use glob::glob;
+use log::{debug, warn};
+use std::fs::{create_dir_all, remove_file, File, OpenOptions};
+use std::io::{BufRead, BufReader, Cursor, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+
+static LOG: &str = "log";
+static OP_SET: u8 = 1;
+static OP_DELETE: u8 = 2;
+static OP_SET_EXPIRING: u8 = 3;
+
+/**
+ * Every record in a log file is framed as:
+ *
+ * |<- crc32c (4) ->|<- payload length (8) ->|<-- payload -->|
+ *
+ * The checksum covers the payload only. A frame that is cut short, or a
+ * last frame whose checksum does not match, marks the end of the usable
+ * log: it is what a crash in the middle of an append leaves behind. A bad
+ * checksum anywhere else is corruption and fails the read.
+ */
+pub struct LogWriter {
+    file: File,
+}
+
+impl LogWriter {
+    pub fn create(path: &Path) -> Result<LogWriter> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(LogWriter { file })
+    }
+
+    /// Start the log at `path` over, dropping whatever an earlier writer left in it.
+    pub fn truncate(path: &Path) -> Result<LogWriter> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(LogWriter { file })
+    }
+
+    pub fn add_record(&mut self, payload: &[u8]) -> Result<()> {
+        let mut buf = Vec::with_capacity(payload.len() + 12);
+        buf.write_u32::<LittleEndian>(crc32c::crc32c(payload))?;
+        buf.write_u64::<LittleEndian>(payload.len() as u64)?;
+        buf.write_all(payload)?;
+        self.file.write_all(&buf)
+    }
+}
+
+pub struct LogReader {
+    reader: BufReader<File>,
+    /// Where the next record starts.
+    offset: u64,
+    dropped_tail: bool,
+}
+
+impl LogReader {
+    pub fn open(path: &Path) -> Result<LogReader> {
+        Ok(LogReader {
+            reader: BufReader::new(File::open(path)?),
+            offset: 0,
+            dropped_tail: false,
+        })
+    }
+
+    /// `true` once a torn record at the end of the log was skipped.
+    pub fn dropped_tail(&self) -> bool {
+        self.dropped_tail
+    }
+
+    /// Read the next record, `None` once the end of the usable log is reached.
+    ///
+    /// A record cut short by the end of the file, or a bad checksum on the
+    /// last record, is what a crash during an append leaves and ends the
+    /// log. A bad checksum with more records after it is corruption.
+    pub fn read_record(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let crc = match self.reader.read_u32::<LittleEndian>() {
+            Ok(crc) => crc,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Ok(self.drop_tail("a torn record"))
+            }
+            Err(e) => return Err(e),
+        };
+        let len = match self.reader.read_u64::<LittleEndian>() {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Ok(self.drop_tail("a torn record"))
+            }
+            Err(e) => return Err(e),
+        };
+        let mut payload = vec![];
+        (&mut self.reader).take(len).read_to_end(&mut payload)?;
+        if payload.len() as u64 != len {
+            return Ok(self.drop_tail("a torn record"));
+        }
+        if crc32c::crc32c(&payload) != crc {
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(self.drop_tail("a record with a bad checksum"));
+            }
+            return Err(futil::corruption(format!(
+                "Bad checksum on the log record at offset {}",
+                self.offset
+            )));
+        }
+        self.offset += 12 + len;
+        Ok(Some(payload))
+    }
+
+    fn drop_tail(&mut self, what: &str) -> Option<Vec<u8>> {
+        warn!("Dropping {} at the end of the log", what);
+        self.dropped_tail = true;
+        None
+    }
+}
+
+/// A mutation as it is recorded in the write-ahead log.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LogRecord {
+    Set {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+    /// A `Set` that expires at `expires_at`, in milliseconds since the Unix epoch.
+    SetExpiring {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: u64,
+    },
+}
+
+impl LogRecord {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        match self {
+            LogRecord::Set { key, value } => {
+                buf.write_u8(OP_SET)?;
+                futil::set_key(&mut buf, key.len(), key)?;
+                futil::set_value(&mut buf, value.len(), value)?;
+            }
+            LogRecord::Delete { key } => {
+                buf.write_u8(OP_DELETE)?;
+                futil::set_key(&mut buf, key.len(), key)?;
+            }
+            LogRecord::SetExpiring {
+                key,
+                value,
+                expires_at,
+            } => {
+                buf.write_u8(OP_SET_EXPIRING)?;
+                futil::set_key(&mut buf, key.len(), key)?;
+                futil::write_varint(&mut buf, *expires_at)?;
+                futil::set_value(&mut buf, value.len(), value)?;
+            }
+        }
+        Ok(buf)
+    }
+
+    fn decode(payload: &[u8]) -> Result<LogRecord> {
+        let mut cursor = Cursor::new(payload);
+        let op = cursor.read_u8()?;
+        let key = futil::get_key(&mut cursor)?;
+        if op == OP_SET {
+            let value = futil::get_value(&mut cursor)?;
+            Ok(LogRecord::Set { key, value })
+        } else if op == OP_DELETE {
+            Ok(LogRecord::Delete { key })
+        } else if op == OP_SET_EXPIRING {
+            let expires_at = futil::read_varint(&mut cursor)?;
+            let value = futil::get_value(&mut cursor)?;
+            Ok(LogRecord::SetExpiring {
+                key,
+                value,
+                expires_at,
+            })
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown write-ahead log operation {}", op),
+            ))
+        }
+    }
+}
+
+/// Durable record of mutations that have not reached an sstable yet.
+///
+/// Logs are numbered files under `dir`. Only the newest one is appended to,
+/// older ones are kept until `remove_obsolete` is called once the memtable
+/// they describe has been written out.
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    number: u64,
+    writer: LogWriter,
+    sync: SyncPolicy,
+}
+
+impl WriteAheadLog {
+    /// Open the log directory, returning the records of every existing log
+    /// (oldest first) that still need to be applied to the memtable.
+    ///
+    /// Logs numbered below `min_number` are already persisted in sstables and are removed.
+    pub fn open(
+        dir: &Path,
+        min_number: u64,
+        sync: SyncPolicy,
+    ) -> Result<(WriteAheadLog, Vec<LogRecord>)> {
+        create_dir_all(dir)?;
+        let mut records = vec![];
+        let mut last_number = 0;
+        for (number, path) in list_logs(dir)? {
+            if number < min_number {
+                debug!("Removing obsolete write-ahead log {}", path.display());
+                remove_file(path)?;
+                continue;
+            }
+            debug!("Replaying write-ahead log {}", path.display());
+            let mut reader = LogReader::open(&path)?;
+            while let Some(payload) = reader.read_record()? {
+                records.push(LogRecord::decode(&payload)?);
+            }
+            last_number = number;
+        }
+        let number = std::cmp::max(last_number + 1, min_number);
+        let writer = LogWriter::create(&log_path(dir, number))?;
+        sync.on_dir_change(dir)?;
+        let wal = WriteAheadLog {
+            dir: dir.to_path_buf(),
+            number,
+            writer,
+            sync,
+        };
+        Ok((wal, records))
+    }
+
+    pub fn append(&mut self, record: &LogRecord) -> Result<()> {
+        self.writer.add_record(&record.encode()?)?;
+        self.sync.on_write(&log_path(&self.dir, self.number))
+    }
+
+    /// Switch to a fresh log, returning its number.
+    pub fn rotate(&mut self) -> Result<u64> {
+        let number = self.number + 1;
+        self.writer = LogWriter::create(&log_path(&self.dir, number))?;
+        self.number = number;
+        self.sync.on_dir_change(&self.dir)?;
+        Ok(number)
+    }
+
+    /// Drop every log numbered below `number`.
+    ///
+    /// Must only be called once the records in those logs are persisted elsewhere.
+    pub fn remove_obsolete(&self, number: u64) -> Result<()> {
+        for (old_number, path) in list_logs(&self.dir)? {
+            if old_number < number {
+                remove_file(path)?;
+            }
+        }
+        self.sync.on_dir_change(&self.dir)
+    }
+}
+
+fn log_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.{}", number, LOG))
+}
+
+fn list_logs(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let glob_pattern = format!("{}/*.{}", dir.display(), LOG);
+    let mut logs = vec![];
+    for path in glob(&glob_pattern)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+        .flatten()
+    {
+        let number = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        match number {
+            Some(number) => logs.push((number, path)),
+            None => warn!("Ignoring unexpected file {}", path.display()),
+        }
+    }
+    logs.sort();
+    Ok(logs)
+}
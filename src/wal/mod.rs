@@ -0,0 +1,3 @@
+pub mod journal;
+#[cfg(test)]
+mod wal_test;
@@ -0,0 +1,159 @@
+#[cfg(test)]
+mod test {
+    use crate::utils::fs::SyncPolicy;
+    use crate::wal::journal::{LogRecord, WriteAheadLog};
+    use std::fs::{self, OpenOptions};
+    use std::io::{ErrorKind, Write};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_replay_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut wal, records) =
+            WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        assert!(records.is_empty());
+        wal.append(&LogRecord::Set {
+            key: b"key1".to_vec(),
+            value: b"value1".to_vec(),
+        })
+        .unwrap();
+        wal.append(&LogRecord::Delete {
+            key: b"key1".to_vec(),
+        })
+        .unwrap();
+        wal.append(&LogRecord::SetExpiring {
+            key: b"key2".to_vec(),
+            value: b"value2".to_vec(),
+            expires_at: 1_700_000_000_000,
+        })
+        .unwrap();
+        drop(wal);
+
+        let (_, records) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                LogRecord::Set {
+                    key: b"key1".to_vec(),
+                    value: b"value1".to_vec()
+                },
+                LogRecord::Delete {
+                    key: b"key1".to_vec()
+                },
+                LogRecord::SetExpiring {
+                    key: b"key2".to_vec(),
+                    value: b"value2".to_vec(),
+                    expires_at: 1_700_000_000_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_torn_tail_is_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut wal, _) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        wal.append(&LogRecord::Set {
+            key: b"key1".to_vec(),
+            value: b"value1".to_vec(),
+        })
+        .unwrap();
+        drop(wal);
+
+        let log_path = temp_dir.path().join("000001.log");
+        let mut file = OpenOptions::new().append(true).open(log_path).unwrap();
+        file.write_all(&[7, 0, 0, 0, 42, 0]).unwrap();
+
+        let (_, records) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn test_corrupt_record_before_the_tail_fails_replay() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut wal, _) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        for key in [b"key1", b"key2", b"key3"] {
+            wal.append(&LogRecord::Set {
+                key: key.to_vec(),
+                value: b"value1".to_vec(),
+            })
+            .unwrap();
+        }
+        drop(wal);
+
+        // Flip a payload byte of the second of three records of the same size.
+        let log_path = temp_dir.path().join("000001.log");
+        let mut bytes = fs::read(&log_path).unwrap();
+        let record_len = bytes.len() / 3;
+        bytes[record_len + 12] ^= 0xff;
+        fs::write(&log_path, bytes).unwrap();
+
+        let result = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default());
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_bad_checksum_on_the_last_record_is_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut wal, _) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        for key in [b"key1", b"key2"] {
+            wal.append(&LogRecord::Set {
+                key: key.to_vec(),
+                value: b"value1".to_vec(),
+            })
+            .unwrap();
+        }
+        drop(wal);
+
+        let log_path = temp_dir.path().join("000001.log");
+        let mut bytes = fs::read(&log_path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&log_path, bytes).unwrap();
+
+        let (_, records) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn test_rotate_removes_old_logs() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut wal, _) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        wal.append(&LogRecord::Delete {
+            key: b"key1".to_vec(),
+        })
+        .unwrap();
+        let number = wal.rotate().unwrap();
+        wal.remove_obsolete(number).unwrap();
+
+        let logs: Vec<_> = fs::read_dir(temp_dir.path()).unwrap().collect();
+        assert_eq!(logs.len(), 1);
+        let (_, records) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn test_open_skips_persisted_logs() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut wal, _) = WriteAheadLog::open(temp_dir.path(), 0, SyncPolicy::default()).unwrap();
+        wal.append(&LogRecord::Delete {
+            key: b"key1".to_vec(),
+        })
+        .unwrap();
+        let number = wal.rotate().unwrap();
+        wal.append(&LogRecord::Delete {
+            key: b"key2".to_vec(),
+        })
+        .unwrap();
+        drop(wal);
+
+        let (_, records) =
+            WriteAheadLog::open(temp_dir.path(), number, SyncPolicy::default()).unwrap();
+        assert_eq!(
+            records,
+            vec![LogRecord::Delete {
+                key: b"key2".to_vec()
+            }]
+        );
+    }
+}